@@ -0,0 +1,164 @@
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use std::error::Error;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_RELAY_PORT: u16 = 4001;
+
+#[derive(Clone, PartialEq)]
+pub enum Mode {
+    // Run the chat application
+    Client,
+    // Run a circuit relay server for peers that are unable to dial each other
+    Relay,
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub mode: Mode,
+    // Relays to reserve a slot on so peers behind NAT can reach us
+    pub relays: Vec<Multiaddr>,
+    // Port used for the default listen addresses, random for peers and 4001 for relays
+    pub port: Option<u16>,
+    // Addresses to listen on instead of the defaults
+    pub listen: Vec<Multiaddr>,
+    // Directory for files that should survive a restart
+    pub data_dir: PathBuf,
+    // Pre-shared key file, which makes this node part of a private network
+    pub psk: Option<PathBuf>,
+    // Key file for a fixed peer id, so room ownership survives a restart
+    pub identity: Option<PathBuf>,
+    // Prefix for every topic and DHT key, keeping teams on the same network apart
+    pub workspace: String,
+    // Send workspace and room names hashed, so passive observers can't list them
+    pub hashed_topics: bool,
+    // How long our nickname and room records live in the DHT without being republished
+    pub record_ttl: Duration,
+    // Number of peers each DHT record is stored on
+    pub replication_factor: NonZeroUsize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Client,
+            relays: vec![],
+            port: None,
+            listen: vec![],
+            data_dir: PathBuf::from("data"),
+            psk: None,
+            identity: None,
+            workspace: "default".to_string(),
+            hashed_topics: false,
+            record_ttl: Duration::from_secs(60 * 60),
+            replication_factor: NonZeroUsize::new(20).unwrap(),
+        }
+    }
+}
+
+impl Config {
+    /// Build the config from the command line arguments
+    ///
+    /// Usage: `swapbytes [relay] [--relay <multiaddr>]... [--listen <multiaddr>]... [--port <port>]
+    /// [--data-dir <dir>] [--psk <file>] [--identity <file>] [--workspace <name>] [--hashed-topics] [--record-ttl <seconds>] [--replication <peers>]`
+    pub fn from_args() -> Result<Self, Box<dyn Error>> {
+        let mut config = Config::default();
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "relay" => config.mode = Mode::Relay,
+                "--relay" => config.relays.push(next_value(&arg, args.next())?.parse()?),
+                "--listen" => config.listen.push(next_value(&arg, args.next())?.parse()?),
+                "--port" => config.port = Some(next_value(&arg, args.next())?.parse()?),
+                "--data-dir" => config.data_dir = PathBuf::from(next_value(&arg, args.next())?),
+                "--psk" => config.psk = Some(PathBuf::from(next_value(&arg, args.next())?)),
+                "--identity" => {
+                    config.identity = Some(PathBuf::from(next_value(&arg, args.next())?))
+                }
+                "--workspace" => {
+                    let workspace = next_value(&arg, args.next())?;
+                    if !is_valid_workspace(&workspace) {
+                        return Err(Box::new(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Invalid workspace name: {workspace}"),
+                        )));
+                    }
+                    config.workspace = workspace;
+                }
+                "--hashed-topics" => config.hashed_topics = true,
+                "--record-ttl" => {
+                    let seconds: u64 = next_value(&arg, args.next())?.parse()?;
+                    // Records are republished at half their TTL, so it can't be too short
+                    if seconds < 60 {
+                        return Err(Box::new(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "Record TTL must be at least 60 seconds",
+                        )));
+                    }
+                    config.record_ttl = Duration::from_secs(seconds);
+                }
+                "--replication" => {
+                    config.replication_factor = next_value(&arg, args.next())?.parse()?
+                }
+                _ => {
+                    return Err(Box::new(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Unknown argument: {arg}"),
+                    )))
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Addresses to listen on, defaulting to TCP and QUIC on every IPv4 and IPv6 interface
+    pub fn listen_addrs(&self) -> Vec<Multiaddr> {
+        if !self.listen.is_empty() {
+            return self.listen.clone();
+        }
+
+        let port = match (self.port, &self.mode) {
+            (Some(port), _) => port,
+            (None, Mode::Client) => 0,
+            (None, Mode::Relay) => DEFAULT_RELAY_PORT,
+        };
+
+        let mut addrs = vec![];
+        for ip in [
+            Protocol::Ip4(Ipv4Addr::UNSPECIFIED),
+            Protocol::Ip6(Ipv6Addr::UNSPECIFIED),
+        ] {
+            let ip_addr = Multiaddr::empty().with(ip);
+            addrs.push(ip_addr.clone().with(Protocol::Tcp(port)));
+            // QUIC can't be used in a private network
+            if self.psk.is_none() {
+                addrs.push(ip_addr.with(Protocol::Udp(port)).with(Protocol::QuicV1));
+            }
+        }
+        addrs
+    }
+}
+
+/// QUIC has its own encryption, so it can't be wrapped by the private network handshake
+pub fn is_quic(addr: &Multiaddr) -> bool {
+    addr.iter().any(|protocol| protocol == Protocol::QuicV1)
+}
+
+/// Workspace names are used as a prefix, so they can't contain the separator
+pub fn is_valid_workspace(workspace: &str) -> bool {
+    !workspace.is_empty() && !workspace.contains('/') && !workspace.contains(' ')
+}
+
+fn next_value(flag: &str, value: Option<String>) -> Result<String, Box<dyn Error>> {
+    value.ok_or_else(|| {
+        Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Missing value for {flag}"),
+        )) as Box<dyn Error>
+    })
+}
@@ -1,8 +1,14 @@
+pub mod config;
 pub mod logger;
 mod network;
+mod record_store;
+mod relay;
 pub mod state;
+mod storage;
+mod transport;
 pub mod ui;
 
+use config::{Config, Mode};
 use state::APP;
 use std::error::Error;
 use tokio::task::spawn;
@@ -14,18 +20,34 @@ use ui::tui;
 async fn main() -> Result<(), Box<dyn Error>> {
     logger::initialize();
 
+    let config = Config::from_args()?;
+
+    // Relay servers have no UI, they only forward traffic between peers
+    if config.mode == Mode::Relay {
+        return relay::run(config).await;
+    }
+
+    APP.lock().unwrap().workspace = config.workspace.clone();
+
     // Setup the network loop
-    let (mut network_client, network_event_loop) = network::new().await?;
+    let (mut network_client, network_event_loop) = network::new(&config).await?;
 
     // Run it in the background
     spawn(network_event_loop.run());
 
-    match network_client
-        .start_listening("/ip4/0.0.0.0/tcp/0".parse()?)
-        .await
-    {
-        Ok(_) => {}
-        Err(e) => logger::info!("Error connecting: {:?}", e),
+    for addr in config.listen_addrs() {
+        if config.psk.is_some() && config::is_quic(&addr) {
+            APP.lock().unwrap().listen_error(format!(
+                "Unable to listen on {addr}: QUIC can't be used in a private network"
+            ));
+            continue;
+        }
+
+        if let Err(e) = network_client.start_listening(addr.clone()).await {
+            APP.lock()
+                .unwrap()
+                .listen_error(format!("Unable to listen on {addr}: {e}"));
+        }
     }
 
     // Setup the UI
@@ -36,7 +58,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let mut chat_screen = ChatScreen::default();
 
     loop {
-        let app = APP.lock().unwrap();
+        let mut app = APP.lock().unwrap();
+        if let Some(status) = network_client.network_status() {
+            app.network_info = status.info;
+            app.room_members = status.room_members;
+        }
         let screen = app.screen.clone();
         drop(app);
 
@@ -1,60 +1,157 @@
 use futures::channel::{mpsc, oneshot};
 use futures::prelude::*;
 use futures::StreamExt;
-use libp2p::gossipsub::IdentTopic;
+use libp2p::gossipsub::{IdentTopic, Sha256Topic, TopicHash};
 
+use crate::config::Config;
 use crate::logger;
-use crate::state::{MessageType, APP};
+use crate::record_store::{DiskStore, SAVE_INTERVAL};
+use crate::state::{unix_time, App, HistoryMessage, MessageType, APP};
+use crate::storage;
+use crate::transport;
 
 use libp2p::{
-    core::Multiaddr,
-    gossipsub,
-    kad::{self, store::MemoryStore, Mode},
-    mdns, noise,
-    request_response::{self, ProtocolSupport, ResponseChannel},
-    swarm::{NetworkBehaviour, Swarm, SwarmEvent},
-    tcp, yamux, PeerId,
+    allow_block_list, autonat, connection_limits,
+    core::{multiaddr::Protocol, Multiaddr},
+    dcutr, gossipsub, identify,
+    identity::Keypair,
+    kad::{self, Mode},
+    mdns, noise, relay,
+    request_response::{self, OutboundRequestId, ProtocolSupport, ResponseChannel},
+    swarm::{ConnectionId, NetworkBehaviour, Swarm, SwarmEvent},
+    yamux, PeerId,
 };
 
+pub use crypto::RoomKey;
+use history::{HistoryEntry, RoomHistory, HISTORY_SYNC_LIMIT};
 use libp2p::StreamProtocol;
+use message::{ChatMessage, RoomMessage};
+use moderation::{minutes_until, Moderation, ModerationLog, ModerationState};
+use registry::{RoomRegistry, GLOBAL_ROOM, MAX_ROOMS, MAX_ROOMS_PER_CREATOR};
+use rooms::{RoomRequest, RoomResponse};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use signed::Signed;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 use std::time::Duration;
+use tokio::sync::watch;
+use validation::{Invalid, RateLimit, RateLimiter};
+
+mod crypto;
+mod diagnostics;
+mod history;
+mod message;
+mod moderation;
+mod registry;
+mod rooms;
+mod signed;
+mod validation;
+
+pub use diagnostics::NetworkInfo;
+pub(crate) use history::HISTORY_LIMIT;
+pub use moderation::{Action, PinnedMessage, RoomDetails};
+pub use registry::{RoomInfo, RoomNames};
+pub(crate) use registry::{MAX_DESCRIPTION_LENGTH, MAX_NAME_LENGTH, MAX_OTHER_NAMES};
+pub use rooms::{Invitation, InviteLink};
+pub(crate) use validation::MAX_MESSAGE_LENGTH;
+
+const MAX_CONNECTIONS: u32 = 64;
+// Allows a relayed and a direct connection to the same peer while hole punching
+const MAX_CONNECTIONS_PER_PEER: u32 = 4;
+const MAX_PENDING_CONNECTIONS: u32 = 16;
+const BLOCKED_PEERS_FILE: &str = "blocked_peers";
+const MODERATION_FILE: &str = "moderation";
+// How often the diagnostics shown in the UI are refreshed
+const NETWORK_INFO_INTERVAL: Duration = Duration::from_secs(1);
+// How often we look for new peers to fill the Kademlia routing table
+const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Number of room members asked for a room's history when joining it
+const HISTORY_PEERS: usize = 3;
+// Number of peers that must store a record before a put counts as a success
+const RECORD_QUORUM: NonZeroUsize = NonZeroUsize::MIN.saturating_add(1);
+// Most of our addresses put in an invite link
+const INVITE_ADDRESSES: usize = 3;
 
 #[derive(NetworkBehaviour)]
 struct Behaviour {
     mdns: mdns::tokio::Behaviour,
-    kademlia: kad::Behaviour<MemoryStore>,
+    kademlia: kad::Behaviour<DiskStore>,
     gossipsub: gossipsub::Behaviour,
     request_response: request_response::cbor::Behaviour<PrivateRequest, PrivateResponse>,
+    rooms: request_response::cbor::Behaviour<RoomRequest, RoomResponse>,
+    relay_client: relay::client::Behaviour,
+    dcutr: dcutr::Behaviour,
+    identify: identify::Behaviour,
+    autonat: autonat::Behaviour,
+    connection_limits: connection_limits::Behaviour,
+    blocked_peers: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
 }
 
-pub(crate) async fn new() -> Result<(Client, EventLoop), Box<dyn Error>> {
-    let key = libp2p::identity::Keypair::generate_ed25519();
+pub(crate) async fn new(config: &Config) -> Result<(Client, EventLoop), Box<dyn Error>> {
+    // A new peer id every run unless a key file is given
+    let key = match &config.identity {
+        Some(path) => storage::load_keypair(path)?,
+        None => Keypair::generate_ed25519(),
+    };
     let peer_id = key.public().to_peer_id();
 
-    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(key)
+    // A kept identity gets its own data directory, so peers sharing one never mix up their state.
+    // Without one the peer id changes every run, so the shared directory is used to keep anything.
+    let config = &Config {
+        data_dir: match &config.identity {
+            Some(_) => config.data_dir.join(peer_id.to_base58()),
+            None => config.data_dir.clone(),
+        },
+        ..config.clone()
+    };
+
+    let psk = match &config.psk {
+        Some(path) => {
+            let psk = transport::load_psk(path)?;
+            logger::info!("Joining private network with key {}", psk.fingerprint());
+            Some(psk)
+        }
+        None => None,
+    };
+
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(key.clone())
         .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
-        .with_behaviour(|key| {
+        .with_other_transport(|key| transport::build(key, psk))?
+        .with_relay_client(noise::Config::new, yamux::Config::default)?
+        .with_behaviour(|key, relay_client| {
             Ok(Behaviour {
                 mdns: mdns::tokio::Behaviour::new(
                     mdns::Config::default(),
                     key.public().to_peer_id(),
                 )?,
-                kademlia: kad::Behaviour::new(
-                    key.public().to_peer_id(),
-                    MemoryStore::new(key.public().to_peer_id()),
-                ),
-                gossipsub: gossipsub::Behaviour::new(
-                    gossipsub::MessageAuthenticity::Signed(key.clone()),
-                    gossipsub::Config::default(),
-                )?,
+                kademlia: {
+                    let mut kad_config = kad::Config::default();
+                    kad_config
+                        .set_record_ttl(Some(config.record_ttl))
+                        .set_replication_factor(config.replication_factor)
+                        .set_replication_interval(Some(config.record_ttl / 2))
+                        // Kademlia keeps the original expiry when it republishes, so we do it ourselves
+                        .set_publication_interval(None);
+                    kad::Behaviour::with_config(
+                        key.public().to_peer_id(),
+                        DiskStore::new(key.public().to_peer_id(), config.data_dir.clone()),
+                        kad_config,
+                    )
+                },
+                gossipsub: {
+                    let mut gossipsub = gossipsub::Behaviour::new(
+                        gossipsub::MessageAuthenticity::Signed(key.clone()),
+                        validation::gossipsub_config()?,
+                    )?;
+                    // Penalise peers that send invalid messages so they are eventually graylisted
+                    gossipsub.with_peer_score(
+                        gossipsub::PeerScoreParams::default(),
+                        gossipsub::PeerScoreThresholds::default(),
+                    )?;
+                    gossipsub
+                },
                 request_response: request_response::cbor::Behaviour::new(
                     [(
                         StreamProtocol::new("/file-exchange/1"),
@@ -62,6 +159,35 @@ pub(crate) async fn new() -> Result<(Client, EventLoop), Box<dyn Error>> {
                     )],
                     request_response::Config::default(),
                 ),
+                rooms: request_response::cbor::Behaviour::new(
+                    [(
+                        StreamProtocol::new("/swapbytes/rooms/1"),
+                        ProtocolSupport::Full,
+                    )],
+                    request_response::Config::default(),
+                ),
+                relay_client,
+                dcutr: dcutr::Behaviour::new(key.public().to_peer_id()),
+                identify: identify::Behaviour::new(
+                    identify::Config::new("/swapbytes/1.0.0".to_string(), key.public())
+                        .with_agent_version(format!("swapbytes/{}", env!("CARGO_PKG_VERSION"))),
+                ),
+                autonat: autonat::Behaviour::new(
+                    key.public().to_peer_id(),
+                    autonat::Config {
+                        // Peers on the same LAN count as reachable
+                        only_global_ips: false,
+                        ..Default::default()
+                    },
+                ),
+                connection_limits: connection_limits::Behaviour::new(
+                    connection_limits::ConnectionLimits::default()
+                        .with_max_established(Some(MAX_CONNECTIONS))
+                        .with_max_established_per_peer(Some(MAX_CONNECTIONS_PER_PEER))
+                        .with_max_pending_incoming(Some(MAX_PENDING_CONNECTIONS))
+                        .with_max_pending_outgoing(Some(MAX_PENDING_CONNECTIONS)),
+                ),
+                blocked_peers: allow_block_list::Behaviour::default(),
             })
         })?
         .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60 * 60))) // 1 hour
@@ -69,21 +195,58 @@ pub(crate) async fn new() -> Result<(Client, EventLoop), Box<dyn Error>> {
 
     let mut app = APP.lock().unwrap();
     app.peer_id = Some(peer_id);
+    app.data_dir = config.data_dir.clone();
     drop(app);
 
-    // Setup kademlia
-    swarm.behaviour_mut().kademlia.set_mode(Some(Mode::Server));
+    // Setup kademlia, assuming we are reachable until AutoNAT says otherwise
+    swarm
+        .behaviour_mut()
+        .kademlia
+        .set_mode(Some(kademlia_mode(&autonat::NatStatus::Unknown)));
+
+    // Keep out anyone who was blocked before the last restart
+    let blocked_peers: HashSet<PeerId> =
+        storage::load::<Vec<String>>(&config.data_dir, BLOCKED_PEERS_FILE)
+            .iter()
+            .filter_map(|peer| peer.parse().ok())
+            .collect();
+    for peer_id in &blocked_peers {
+        swarm.behaviour_mut().blocked_peers.block_peer(*peer_id);
+    }
+
+    // Reserve a slot on each relay so peers on other subnets can reach us
+    for relay_addr in &config.relays {
+        swarm.dial(relay_addr.clone())?;
+        swarm.listen_on(relay_addr.clone().with(Protocol::P2pCircuit))?;
+    }
 
     let (command_sender, command_receiver) = mpsc::channel(0);
+    let (status_sender, status_receiver) = watch::channel(NetworkStatus::default());
 
     Ok((
         Client {
             sender: command_sender,
+            status: status_receiver,
         },
-        EventLoop::new(swarm, command_receiver),
+        EventLoop::new(
+            swarm,
+            command_receiver,
+            status_sender,
+            config,
+            key,
+            blocked_peers,
+        ),
     ))
 }
 
+/// What the network looks like, sent to the UI every `NETWORK_INFO_INTERVAL`
+#[derive(Clone, Default)]
+pub(crate) struct NetworkStatus {
+    pub(crate) info: NetworkInfo,
+    // Peers subscribed to each room, including ourselves
+    pub(crate) room_members: HashMap<String, usize>,
+}
+
 #[derive(Debug)]
 enum Command {
     StartListening {
@@ -94,9 +257,62 @@ enum Command {
         topic: String,
         sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
     },
+    LeaveTopic {
+        topic: String,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+    },
+    AddRoomKey {
+        room: String,
+        key: RoomKey,
+        owner: Option<PeerId>,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+    },
+    // Share a password room's info encrypted with its key, so joiners can check their password
+    PublishRoomInfo {
+        room: String,
+        info: RoomInfo,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+    },
+    FetchRoomInfo {
+        room: String,
+        key: RoomKey,
+        sender: oneshot::Sender<Result<Option<RoomInfo>, Box<dyn Error + Send>>>,
+    },
+    SendInvite {
+        peer_id: PeerId,
+        invitation: Invitation,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+    },
+    ChangeWorkspace {
+        workspace: String,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+    },
+    InviteAddresses {
+        sender: oneshot::Sender<Result<Vec<Multiaddr>, Box<dyn Error + Send>>>,
+    },
+    Dial {
+        addresses: Vec<Multiaddr>,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+    },
     SendTopicMessage {
         message: String,
         topic: IdentTopic,
+        // Replies with the message's id
+        sender: oneshot::Sender<Result<String, Box<dyn Error + Send>>>,
+    },
+    Moderate {
+        room: String,
+        action: Action,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+    },
+    RoomDetails {
+        room: String,
+        sender: oneshot::Sender<Result<RoomDetails, Box<dyn Error + Send>>>,
+    },
+    RequestHistory {
+        room: String,
+        limit: usize,
+        since: Option<u64>,
         sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
     },
     AddNickname {
@@ -105,7 +321,19 @@ enum Command {
         sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
     },
     AddRoom {
-        rooms: Vec<String>,
+        room: String,
+        info: RoomInfo,
+        // Replies with the info signed by us as the room's creator
+        sender: oneshot::Sender<Result<RoomInfo, Box<dyn Error + Send>>>,
+    },
+    SignRoom {
+        room: String,
+        info: RoomInfo,
+        sender: oneshot::Sender<Result<RoomInfo, Box<dyn Error + Send>>>,
+    },
+    SetRoomNames {
+        room: String,
+        names: RoomNames,
         sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
     },
     FetchRooms {
@@ -118,14 +346,31 @@ enum Command {
         filename: Option<String>,
         sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
     },
+    BlockPeer {
+        peer_id: PeerId,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+    },
+    UnblockPeer {
+        peer_id: PeerId,
+        sender: oneshot::Sender<Result<(), Box<dyn Error + Send>>>,
+    },
 }
 
 #[derive(Clone)]
 pub(crate) struct Client {
     sender: mpsc::Sender<Command>,
+    status: watch::Receiver<NetworkStatus>,
 }
 
 impl Client {
+    /// The latest network status, if it has changed since it was last taken
+    pub(crate) fn network_status(&mut self) -> Option<NetworkStatus> {
+        match self.status.has_changed() {
+            Ok(true) => Some(self.status.borrow_and_update().clone()),
+            _ => None,
+        }
+    }
+
     pub(crate) async fn start_listening(
         &mut self,
         addr: Multiaddr,
@@ -150,104 +395,1047 @@ impl Client {
         receiver.await.expect("Sender not to be dropped.")
     }
 
-    pub(crate) async fn publish_message(
-        &mut self,
-        message: String,
-        topic: IdentTopic,
-    ) -> Result<(), Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::SendTopicMessage {
-                message,
-                topic,
-                sender,
-            })
-            .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
+    pub(crate) async fn leave_topic(&mut self, topic: String) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::LeaveTopic { topic, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn add_room_key(
+        &mut self,
+        room: String,
+        key: RoomKey,
+        owner: Option<PeerId>,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::AddRoomKey {
+                room,
+                key,
+                owner,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    /// Store a password room's info in the DHT, encrypted with the room's key
+    pub(crate) async fn publish_room_info(
+        &mut self,
+        room: String,
+        info: RoomInfo,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::PublishRoomInfo { room, info, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    /// Find a password room's info, failing if it was encrypted with a different key.
+    /// Returns None if nobody has published it yet.
+    pub(crate) async fn fetch_room_info(
+        &mut self,
+        room: String,
+        key: RoomKey,
+    ) -> Result<Option<RoomInfo>, Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::FetchRoomInfo { room, key, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn send_invite(
+        &mut self,
+        peer_id: PeerId,
+        invitation: Invitation,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::SendInvite {
+                peer_id,
+                invitation,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn change_workspace(
+        &mut self,
+        workspace: String,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::ChangeWorkspace { workspace, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    /// Addresses other peers can dial us on, for sharing in an invite link
+    pub(crate) async fn invite_addresses(
+        &mut self,
+    ) -> Result<Vec<Multiaddr>, Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::InviteAddresses { sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    /// Connect to peers from an invite link and use them to find the rest of the workspace
+    pub(crate) async fn dial(
+        &mut self,
+        addresses: Vec<Multiaddr>,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::Dial { addresses, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn publish_message(
+        &mut self,
+        message: String,
+        topic: IdentTopic,
+    ) -> Result<String, Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::SendTopicMessage {
+                message,
+                topic,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn moderate(
+        &mut self,
+        room: String,
+        action: Action,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::Moderate {
+                room,
+                action,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn room_details(
+        &mut self,
+        room: String,
+    ) -> Result<RoomDetails, Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::RoomDetails { room, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn request_history(
+        &mut self,
+        room: String,
+        limit: usize,
+        since: Option<u64>,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::RequestHistory {
+                room,
+                limit,
+                since,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn add_nickname(
+        &mut self,
+        nickname: String,
+        peer_id: PeerId,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::AddNickname {
+                nickname,
+                peer_id,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    /// Add a public room to the registry, returning its info signed by us
+    pub(crate) async fn add_room(
+        &mut self,
+        room: String,
+        info: RoomInfo,
+    ) -> Result<RoomInfo, Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::AddRoom { room, info, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    /// Sign a private room's info as its creator, so invited peers can tell who owns it
+    pub(crate) async fn sign_room(
+        &mut self,
+        room: String,
+        info: RoomInfo,
+    ) -> Result<RoomInfo, Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::SignRoom { room, info, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn set_room_names(
+        &mut self,
+        room: String,
+        names: RoomNames,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::SetRoomNames {
+                room,
+                names,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn fetch_rooms(&mut self) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::FetchRooms { sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn send_request(
+        &mut self,
+        peer_id: PeerId,
+        request_type: RequestType,
+        message: Option<String>,
+        filename: Option<String>,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::SendRequest {
+                peer_id,
+                request_type,
+                message,
+                filename,
+                sender,
+            })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn block_peer(
+        &mut self,
+        peer_id: PeerId,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::BlockPeer { peer_id, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+
+    pub(crate) async fn unblock_peer(
+        &mut self,
+        peer_id: PeerId,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Command::UnblockPeer { peer_id, sender })
+            .await
+            .expect("Command receiver not to be dropped.");
+        receiver.await.expect("Sender not to be dropped.")
+    }
+}
+
+// Looking for a password room's info, which only decrypts with the right password
+struct RoomInfoQuery {
+    room: String,
+    key: RoomKey,
+    // Whether a record was found that the key couldn't decrypt
+    wrong_key: bool,
+    sender: oneshot::Sender<Result<Option<RoomInfo>, Box<dyn Error + Send>>>,
+}
+
+pub(crate) struct EventLoop {
+    swarm: Swarm<Behaviour>,
+    // Our identity, used to sign invitations
+    keypair: Keypair,
+    command_receiver: mpsc::Receiver<Command>,
+    // Sent to the UI rather than written to APP, so the event loop never waits on the UI
+    status_sender: watch::Sender<NetworkStatus>,
+    // Chat messages waiting on their author's nickname, with the id they were signed with
+    stored_messages: HashMap<String, (String, gossipsub::Message)>,
+    stored_private_messages: HashMap<String, PrivateRequest>,
+    discovered_peers: HashSet<PeerId>,
+    workspace: String,
+    // Hide workspace and room names from peers that don't already know them
+    hashed_topics: bool,
+    // Maps each subscribed topic back to the room it belongs to
+    topics: HashMap<TopicHash, String>,
+    blocked_peers: HashSet<PeerId>,
+    data_dir: PathBuf,
+    rate_limiter: RateLimiter,
+    // Open connections and the address each one goes to
+    connections: HashMap<ConnectionId, (PeerId, Multiaddr)>,
+    // Records we published, which are republished before they expire
+    own_records: HashMap<kad::RecordKey, Vec<u8>>,
+    record_ttl: Duration,
+    rooms: RoomRegistry,
+    // Keys for the private rooms we are a member of
+    room_keys: HashMap<String, RoomKey>,
+    // Private rooms aren't in the registry, so their creators are kept here
+    private_owners: HashMap<String, PeerId>,
+    // Password checks waiting on the DHT
+    room_info_queries: HashMap<kad::QueryId, RoomInfoQuery>,
+    // Signed moderation actions for each room in the workspace
+    moderation: HashMap<String, ModerationLog>,
+    // Recent messages in each subscribed room, for peers that join after us
+    history: HashMap<TopicHash, RoomHistory>,
+    history_requests: HashMap<OutboundRequestId, String>,
+    // Rooms joined when no other members were known, synced once one turns up
+    pending_history: HashSet<String>,
+}
+
+impl EventLoop {
+    fn new(
+        swarm: Swarm<Behaviour>,
+        command_receiver: mpsc::Receiver<Command>,
+        status_sender: watch::Sender<NetworkStatus>,
+        config: &Config,
+        keypair: Keypair,
+        blocked_peers: HashSet<PeerId>,
+    ) -> Self {
+        let mut event_loop = Self {
+            swarm,
+            keypair,
+            command_receiver,
+            status_sender,
+            stored_messages: HashMap::new(),
+            stored_private_messages: HashMap::new(),
+            discovered_peers: HashSet::new(),
+            workspace: config.workspace.clone(),
+            hashed_topics: config.hashed_topics,
+            topics: HashMap::new(),
+            blocked_peers,
+            data_dir: config.data_dir.clone(),
+            rate_limiter: RateLimiter::default(),
+            connections: HashMap::new(),
+            own_records: HashMap::new(),
+            record_ttl: config.record_ttl,
+            rooms: RoomRegistry::default(),
+            room_keys: HashMap::new(),
+            private_owners: HashMap::new(),
+            room_info_queries: HashMap::new(),
+            moderation: HashMap::new(),
+            history: HashMap::new(),
+            history_requests: HashMap::new(),
+            pending_history: HashSet::new(),
+        };
+        event_loop.subscribe_registry();
+        event_loop.load_moderation();
+        event_loop
+    }
+
+    fn save_blocked_peers(&self) -> std::io::Result<()> {
+        let peers: Vec<String> = self
+            .blocked_peers
+            .iter()
+            .map(|peer_id| peer_id.to_base58())
+            .collect();
+        storage::save(&self.data_dir, BLOCKED_PEERS_FILE, &peers)
+    }
+
+    // Put a record we own, remembering it so it can be republished before it expires
+    fn put_record(
+        &mut self,
+        key: kad::RecordKey,
+        value: Vec<u8>,
+    ) -> Result<kad::QueryId, kad::store::Error> {
+        self.own_records.insert(key.clone(), value.clone());
+        let record = kad::Record {
+            key,
+            value,
+            publisher: None,
+            // Filled in from the configured record TTL
+            expires: None,
+        };
+        self.swarm
+            .behaviour_mut()
+            .kademlia
+            .put_record(record, kad::Quorum::N(RECORD_QUORUM))
+    }
+
+    fn republish_records(&mut self) {
+        let records: Vec<(kad::RecordKey, Vec<u8>)> = self
+            .own_records
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        for (key, value) in records {
+            logger::info!("Republishing record {:?}", key);
+            if let Err(e) = self.put_record(key, value) {
+                logger::error!("Failed to republish record: {:?}", e);
+            }
+        }
+    }
+
+    // The peer a nickname record belongs to, if the key is for a nickname in this workspace
+    fn nickname_peer(&self, key: &kad::RecordKey) -> Option<PeerId> {
+        let key_str = String::from_utf8_lossy(key.as_ref());
+        key_str.strip_prefix(&self.nickname_prefix())?.parse().ok()
+    }
+
+    // Topics and DHT keys are prefixed with the workspace, so workspaces never see each other
+    fn scoped_topic(&self, room: &str) -> IdentTopic {
+        match self.room_keys.get(room) {
+            // Private room topics come from the key, so they don't reveal the room's name
+            Some(key) => self.topic(format!("{}#private/{}", self.workspace, key.topic_id())),
+            None => self.topic(format!("{}/{}", self.workspace, room)),
+        }
+    }
+
+    fn topic(&self, name: String) -> IdentTopic {
+        match self.hashed_topics {
+            // The same hash a `Sha256Topic` sends, kept as an `IdentTopic` so every room
+            // has the same type
+            true => IdentTopic::new(Sha256Topic::new(name).hash().into_string()),
+            false => IdentTopic::new(name),
+        }
+    }
+
+    // A workspace or room name as it appears in DHT keys
+    fn hidden(&self, name: &str) -> String {
+        match self.hashed_topics {
+            true => RoomKey::from_name(name).topic_id(),
+            false => name.to_string(),
+        }
+    }
+
+    // With hashed topics, public rooms and the registry are encrypted with a key derived from
+    // their topic's name, so only peers that know the name can read them
+    fn name_key(&self, name: &str) -> Option<RoomKey> {
+        self.hashed_topics.then(|| RoomKey::from_name(name))
+    }
+
+    fn room_key(&self, room: &str) -> Option<RoomKey> {
+        match self.room_keys.get(room) {
+            Some(key) => Some(key.clone()),
+            None => self.name_key(&format!("{}/{}", self.workspace, room)),
+        }
+    }
+
+    // Decode a room message, decrypting it first if the room is private
+    fn decode_message(&self, room: &str, data: &[u8]) -> Result<RoomMessage, Invalid> {
+        let data = match self.room_key(room) {
+            Some(key) => key.decrypt(data).ok_or(Invalid::WrongKey)?,
+            None => data.to_vec(),
+        };
+        let message = RoomMessage::from_bytes(&data).map_err(|_| Invalid::Malformed)?;
+        if let RoomMessage::Chat(signed) = &message {
+            self.verify_chat(room, signed)?;
+        }
+        Ok(message)
+    }
+
+    // Check a chat message was signed for this room, returning its author and the message
+    fn verify_chat(&self, room: &str, signed: &Signed) -> Result<(PeerId, ChatMessage), Invalid> {
+        let (author, chat): (PeerId, ChatMessage) = signed.verify().ok_or(Invalid::Forged)?;
+        if chat.workspace != self.workspace
+            || chat.room != room
+            || !chat.id.starts_with(&author.to_base58())
+        {
+            return Err(Invalid::Forged);
+        }
+        validation::validate_text(&chat.text)?;
+        Ok((author, chat))
+    }
+
+    // The author and text of a chat message, if it is one
+    fn decode_chat(&self, room: &str, data: &[u8]) -> Option<(PeerId, ChatMessage)> {
+        match self.decode_message(room, data) {
+            Ok(RoomMessage::Chat(signed)) => self.verify_chat(room, &signed).ok(),
+            _ => None,
+        }
+    }
+
+    // The text of a chat message that has already been validated
+    fn chat_text(&self, room: &str, data: &[u8]) -> String {
+        self.decode_chat(room, data)
+            .map(|(_, chat)| chat.text)
+            .unwrap_or_default()
+    }
+
+    // Publish a message to a room, encrypting it if the room is private
+    fn publish_to_room(
+        &mut self,
+        room: &str,
+        message: &RoomMessage,
+    ) -> Result<gossipsub::MessageId, Box<dyn Error + Send>> {
+        let data = message
+            .to_bytes()
+            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+        let data = match self.room_key(room) {
+            Some(key) => key.encrypt(&data),
+            None => data,
+        };
+        let topic = self.scoped_topic(room);
+        let message_id = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic.clone(), data.clone())
+            .map_err(|e| Box::new(e) as Box<dyn Error + Send>)?;
+
+        if let Some((author, chat)) = match message {
+            RoomMessage::Chat(signed) => signed.verify::<ChatMessage>(),
+            _ => None,
+        } {
+            let entry = HistoryEntry {
+                id: chat.id,
+                author,
+                data,
+                sent_at: chat.sent_at,
+            };
+            self.history.entry(topic.hash()).or_default().insert(entry);
+        }
+        Ok(message_id)
+    }
+
+    // Ask members of a room for the messages sent before we joined, returning how many were asked
+    fn request_history(&mut self, room: &str, limit: usize, since: Option<u64>) -> usize {
+        let topic = self.scoped_topic(room).hash();
+        let members: Vec<PeerId> = self
+            .swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .filter(|(_, topics)| topics.contains(&&topic))
+            .map(|(peer_id, _)| *peer_id)
+            .take(HISTORY_PEERS)
+            .collect();
+        for peer_id in &members {
+            self.send_history_request(peer_id, room, limit, since);
+        }
+        members.len()
+    }
+
+    fn send_history_request(
+        &mut self,
+        peer_id: &PeerId,
+        room: &str,
+        limit: usize,
+        since: Option<u64>,
+    ) {
+        let request = RoomRequest::History {
+            topic: self.scoped_topic(room).hash().into_string(),
+            limit,
+            since,
+        };
+        let request_id = self
+            .swarm
+            .behaviour_mut()
+            .rooms
+            .send_request(peer_id, request);
+        self.history_requests.insert(request_id, room.to_string());
+    }
+
+    // Our recent messages in a room, if we are a member of it
+    fn room_history(&self, topic: String, limit: usize, since: Option<u64>) -> Vec<HistoryEntry> {
+        let topic = TopicHash::from_raw(topic);
+        let (room, history) = match (self.topics.get(&topic), self.history.get(&topic)) {
+            (Some(room), Some(history)) => (room, history),
+            _ => return vec![],
+        };
+
+        // Deleted messages are never passed on
+        let state = self.moderation_state(room);
+        history
+            .recent(limit, since)
+            .into_iter()
+            .filter(|entry| !state.is_deleted(&entry.id))
+            .collect()
+    }
+
+    fn handle_history(
+        &mut self,
+        request_id: OutboundRequestId,
+        peer: PeerId,
+        entries: Vec<HistoryEntry>,
+    ) {
+        let room = match self.history_requests.remove(&request_id) {
+            Some(room) => room,
+            None => return,
+        };
+        // The room may have been left while waiting
+        let topic = self.scoped_topic(&room).hash();
+        if !self.topics.contains_key(&topic) {
+            return;
+        }
+
+        // Only the signed message is trusted, and it goes through the same checks as a live one
+        let state = self.moderation_state(&room);
+        let now = unix_time();
+        let mut history = vec![];
+        for entry in entries {
+            let (author, chat) = match self.decode_chat(&room, &entry.data) {
+                Some(chat) => chat,
+                None => continue,
+            };
+            // The id is taken from the signed message, so a responder can't pass off a
+            // message under another id, or hide one behind an id we already have
+            if state.is_deleted(&chat.id)
+                || state.is_banned(&author)
+                || state.muted_until(&author, now).is_some()
+                || self.blocked_peers.contains(&author)
+                || !self.can_post(&room, &author)
+            {
+                continue;
+            }
+            let sent_at = chat.sent_at.min(now);
+            history.push(HistoryMessage {
+                id: chat.id.clone(),
+                author,
+                text: chat.text,
+                sent_at,
+            });
+            // Keep them too, so we can pass them on to peers that join after us
+            let entry = HistoryEntry {
+                id: chat.id,
+                author,
+                sent_at,
+                ..entry
+            };
+            self.history.entry(topic.clone()).or_default().insert(entry);
+        }
+
+        logger::info!(
+            "Received {} earlier messages in {room} from {peer}",
+            history.len()
+        );
+        // Pins may have been waiting on these messages for their text
+        let details = self.room_details(&room);
+        let mut app = APP.lock().unwrap();
+        app.merge_history(&room, history);
+        app.room_details.insert(room, details);
+        drop(app);
+    }
+
+    // A room's topic, publishers and pins, with each pin's text taken from our history
+    fn room_details(&self, room: &str) -> RoomDetails {
+        let state = self.moderation_state(room);
+        let history = self.history.get(&self.scoped_topic(room).hash());
+        let pins = state
+            .pins()
+            .iter()
+            .map(|id| {
+                let chat = history
+                    .and_then(|history| history.get(id))
+                    .and_then(|entry| self.decode_chat(room, &entry.data));
+                PinnedMessage {
+                    id: id.clone(),
+                    author: chat.as_ref().map(|(author, _)| *author),
+                    text: chat.map(|(_, chat)| chat.text),
+                }
+            })
+            .collect();
+        RoomDetails {
+            topic: state.topic().to_string(),
+            pins,
+            publishers: state.publishers().to_vec(),
+        }
+    }
+
+    // Anyone can post in a room unless it is for announcements
+    fn can_post(&self, room: &str, peer: &PeerId) -> bool {
+        match self.rooms.get(room) {
+            Some(info) if info.announcement && !self.room_keys.contains_key(room) => {
+                self.moderation_state(room).can_publish(peer)
+            }
+            _ => true,
+        }
+    }
+
+    // The creator of a room, who is the only one that can appoint moderators
+    fn room_owner(&self, room: &str) -> Option<PeerId> {
+        match self.room_keys.contains_key(room) {
+            true => self.private_owners.get(room).copied(),
+            false => self.rooms.get(room).and_then(|info| info.creator),
+        }
+    }
+
+    fn moderation_state(&self, room: &str) -> ModerationState {
+        let owner = self.room_owner(room);
+        match self.moderation.get(room) {
+            Some(log) => log.state(owner),
+            None => ModerationLog::default().state(owner),
+        }
+    }
+
+    // Moderation logs are saved for every workspace, so bans survive a restart
+    fn load_moderation(&mut self) {
+        let mut saved: HashMap<String, HashMap<String, Vec<Signed>>> =
+            storage::load(&self.data_dir, MODERATION_FILE);
+        self.moderation = saved
+            .remove(&self.workspace)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(room, signed)| (room, ModerationLog::from_signed(signed)))
+            .collect();
+    }
+
+    fn save_moderation(&self) {
+        let mut saved: HashMap<String, HashMap<String, Vec<Signed>>> =
+            storage::load(&self.data_dir, MODERATION_FILE);
+        let logs = self
+            .moderation
+            .iter()
+            .map(|(room, log)| (room.clone(), log.signed()))
+            .collect();
+        saved.insert(self.workspace.clone(), logs);
+        if let Err(e) = storage::save(&self.data_dir, MODERATION_FILE, &saved) {
+            logger::error!("Unable to save moderation logs: {:?}", e);
+        }
+    }
+
+    // Apply a moderation action if it is new and allowed, returning who took it
+    fn apply_moderation(&mut self, room: &str, signed: Signed) -> Option<(PeerId, Action)> {
+        let owner = self.room_owner(room);
+        let workspace = self.workspace.clone();
+        let (signer, action) = self
+            .moderation
+            .entry(room.to_string())
+            .or_default()
+            .insert(signed, &workspace, room, owner)?;
+        self.save_moderation();
+        Some((signer, action))
+    }
+
+    // Show an action taken by another peer, leaving the room if it was aimed at us
+    fn handle_moderation(&mut self, room: &str, signed: Signed, announce: bool) {
+        let (signer, action) = match self.apply_moderation(room, signed) {
+            Some(applied) => applied,
+            None => return,
+        };
+
+        let local_peer_id = *self.swarm.local_peer_id();
+        let details = self.room_details(room);
+        let room = room.to_string();
+        let mut app = APP.lock().unwrap();
+        app.room_details.insert(room.clone(), details);
+        let by = app.display_name(&signer);
+        match &action {
+            Action::Delete(message_id) => app.delete_message(&room, message_id),
+            Action::Kick(peer) | Action::Ban(peer) if *peer == local_peer_id => {
+                // Honest peers leave the room themselves, which the UI does on its next update
+                app.removed_from.push(room.clone());
+                let verb = match action {
+                    Action::Kick(_) => "kicked",
+                    _ => "banned",
+                };
+                app.add_message(
+                    MessageType::Error,
+                    format!("You have been {verb} from {room} by {by}"),
+                    Some(&GLOBAL_ROOM.to_string()),
+                );
+            }
+            _ if announce => {
+                let target = action
+                    .target()
+                    .map(|peer| app.display_name(&peer))
+                    .unwrap_or_default();
+                app.add_message(MessageType::Info, action.notice(&target, &by), Some(&room));
+            }
+            _ => {}
+        }
+        drop(app);
+    }
+
+    // Moderators send the log to peers joining the room, so they know who is banned or muted
+    fn share_moderation_log(&mut self, room: &str) {
+        let local_peer_id = *self.swarm.local_peer_id();
+        if !self.moderation_state(room).can_moderate(&local_peer_id) {
+            return;
+        }
+        let log = match self.moderation.get(room) {
+            Some(log) if !log.is_empty() => log.signed(),
+            _ => return,
+        };
+        if let Err(e) = self.publish_to_room(room, &RoomMessage::ModerationLog(log)) {
+            logger::error!("Unable to share the moderation log for {room}: {:?}", e);
+        }
+    }
+
+    // Store a public room's moderation log in the DHT, so its topic and pins reach peers
+    // that join while no moderator is online
+    fn store_room_log(&mut self, room: &str) {
+        if self.room_keys.contains_key(room) {
+            return;
+        }
+        let log = match self.moderation.get(room) {
+            Some(log) => log.signed(),
+            None => return,
+        };
+        let result = serde_cbor::to_vec(&log)
+            .map(|bytes| match self.room_key(room) {
+                Some(key) => key.encrypt(&bytes),
+                None => bytes,
+            })
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|bytes| {
+                let key = self.room_log_key(room);
+                self.put_record(key, bytes).map_err(|e| format!("{:?}", e))
+            });
+        if let Err(e) = result {
+            logger::error!("Unable to store the moderation log for {room}: {}", e);
+        }
+    }
+
+    // Rooms are always "{workspace}/{room}", so this can't clash with a room's topic
+    fn registry_topic(&self) -> IdentTopic {
+        self.topic(format!("{}#rooms", self.workspace))
+    }
+
+    fn registry_bytes(&self) -> Result<Vec<u8>, serde_cbor::Error> {
+        let bytes = self.rooms.to_bytes()?;
+        Ok(match self.name_key(&format!("{}#rooms", self.workspace)) {
+            Some(key) => key.encrypt(&bytes),
+            None => bytes,
+        })
+    }
+
+    // Rooms sent by another peer or stored in the DHT, decrypted first if topics are hashed
+    fn parse_registry(&self, data: &[u8]) -> Option<BTreeMap<String, RoomInfo>> {
+        let data = match self.name_key(&format!("{}#rooms", self.workspace)) {
+            Some(key) => key.decrypt(data)?,
+            None => data.to_vec(),
+        };
+        RoomRegistry::from_bytes(&data)
+            .ok()
+            .filter(|rooms| rooms.len() <= MAX_ROOMS)
+    }
+
+    fn subscribe_registry(&mut self) {
+        let topic = self.registry_topic();
+        if let Err(e) = self.swarm.behaviour_mut().gossipsub.subscribe(&topic) {
+            logger::error!("Unable to subscribe to the room registry: {:?}", e);
+        }
+        let _ = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .set_topic_params(topic, validation::topic_score_params());
+    }
+
+    // Send our whole room registry to the other peers in the workspace
+    fn publish_registry(&mut self) {
+        let bytes = match self.registry_bytes() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                logger::error!("Unable to encode rooms: {:?}", e);
+                return;
+            }
+        };
+
+        let topic = self.registry_topic();
+        if let Err(e) = self.swarm.behaviour_mut().gossipsub.publish(topic, bytes) {
+            // Nobody else is subscribed yet, they will get the rooms when they join
+            logger::info!("Unable to publish rooms: {:?}", e);
+        }
+    }
+
+    // Store our room registry in the DHT for peers that join later
+    fn store_registry(&mut self) {
+        let result = self
+            .registry_bytes()
+            .map_err(|e| format!("{:?}", e))
+            .and_then(|bytes| {
+                let key = self.rooms_key();
+                self.put_record(key, bytes).map_err(|e| format!("{:?}", e))
+            });
+        if let Err(e) = result {
+            logger::error!("Unable to store rooms: {}", e);
+        }
+    }
+
+    // Merge rooms learned from another peer or the DHT, and pass on anything they were missing
+    fn merge_rooms(&mut self, rooms: BTreeMap<String, RoomInfo>) {
+        if self.rooms.merge(&rooms, &self.workspace.clone()) {
+            let mut app = APP.lock().unwrap();
+            // Private rooms are only known to us, so keep them in the list
+            let mut private_rooms: Vec<String> = self.room_keys.keys().cloned().collect();
+            private_rooms.sort();
+            app.rooms = self.rooms.rooms();
+            for room in private_rooms {
+                if !app.rooms.contains(&room) {
+                    app.rooms.push(room);
+                }
+            }
+            for (room, info) in self.rooms.infos() {
+                app.room_info.insert(room.clone(), info.clone());
+                if let Some(names) = info.verified_names(&self.workspace, room) {
+                    app.room_names.insert(room.clone(), names);
+                }
+            }
+            drop(app);
+        }
+
+        if self.rooms.is_ahead_of(&rooms, &self.workspace) {
+            self.publish_registry();
+            self.store_registry();
+        }
     }
 
-    pub(crate) async fn add_nickname(
-        &mut self,
-        nickname: String,
-        peer_id: PeerId,
-    ) -> Result<(), Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::AddNickname {
-                nickname,
-                peer_id,
-                sender,
-            })
-            .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
+    fn rooms_key(&self) -> kad::RecordKey {
+        kad::RecordKey::new(&format!("{}/rooms", self.hidden(&self.workspace)))
     }
 
-    pub(crate) async fn add_room(
-        &mut self,
-        rooms: Vec<String>,
-    ) -> Result<(), Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::AddRoom { rooms, sender })
-            .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
+    fn room_log_key(&self, room: &str) -> kad::RecordKey {
+        kad::RecordKey::new(&format!(
+            "{}/rooms/{}/log",
+            self.hidden(&self.workspace),
+            self.hidden(room)
+        ))
     }
 
-    pub(crate) async fn fetch_rooms(&mut self) -> Result<(), Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::FetchRooms { sender })
-            .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
+    // Always hidden, so private room names never appear in the DHT
+    fn private_info_key(&self, room: &str) -> kad::RecordKey {
+        let name = format!("{}/{}", self.workspace, room);
+        kad::RecordKey::new(&format!(
+            "{}/private/{}",
+            self.hidden(&self.workspace),
+            RoomKey::from_name(&name).topic_id()
+        ))
     }
 
-    pub(crate) async fn send_request(
+    // Any record the key can decrypt proves the password. The query only fails once every
+    // record found was encrypted with another key, so nobody can lock others out by
+    // publishing garbage.
+    fn handle_room_info_query(
         &mut self,
-        peer_id: PeerId,
-        request_type: RequestType,
-        message: Option<String>,
-        filename: Option<String>,
-    ) -> Result<(), Box<dyn Error + Send>> {
-        let (sender, receiver) = oneshot::channel();
-        self.sender
-            .send(Command::SendRequest {
-                peer_id,
-                request_type,
-                message,
-                filename,
-                sender,
-            })
-            .await
-            .expect("Command receiver not to be dropped.");
-        receiver.await.expect("Sender not to be dropped.")
+        id: kad::QueryId,
+        result: Result<kad::GetRecordOk, kad::GetRecordError>,
+        last: bool,
+    ) {
+        let query = match self.room_info_queries.get_mut(&id) {
+            Some(query) => query,
+            None => return,
+        };
+        if let Ok(kad::GetRecordOk::FoundRecord(kad::PeerRecord { record, .. })) = result {
+            match query.key.decrypt(&record.value) {
+                Some(bytes) => {
+                    let info = serde_cbor::from_slice::<RoomInfo>(&bytes).ok();
+                    if let Some(query) = self.room_info_queries.remove(&id) {
+                        let _ = query.sender.send(Ok(info));
+                    }
+                    if let Some(mut query) = self.swarm.behaviour_mut().kademlia.query_mut(&id) {
+                        query.finish();
+                    }
+                    return;
+                }
+                None => query.wrong_key = true,
+            }
+        }
+        if !last {
+            return;
+        }
+        if let Some(query) = self.room_info_queries.remove(&id) {
+            let _ = match query.wrong_key {
+                true => query.sender.send(Err(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!("Wrong password for {}", query.room),
+                )))),
+                false => query.sender.send(Ok(None)),
+            };
+        }
     }
-}
 
-pub(crate) struct EventLoop {
-    swarm: Swarm<Behaviour>,
-    command_receiver: mpsc::Receiver<Command>,
-    stored_messages: HashMap<String, gossipsub::Message>,
-    stored_private_messages: HashMap<String, PrivateRequest>,
-}
+    // The subscribed public room a moderation log record belongs to
+    fn room_log_room(&self, key: &kad::RecordKey) -> Option<String> {
+        let key_str = String::from_utf8_lossy(key.as_ref());
+        let hidden_room = key_str
+            .strip_prefix(&format!("{}/rooms/", self.hidden(&self.workspace)))?
+            .strip_suffix("/log")?;
+        self.topics
+            .values()
+            .find(|room| self.hidden(room) == hidden_room && !self.room_keys.contains_key(*room))
+            .cloned()
+    }
 
-impl EventLoop {
-    fn new(swarm: Swarm<Behaviour>, command_receiver: mpsc::Receiver<Command>) -> Self {
-        Self {
-            swarm,
-            command_receiver,
-            stored_messages: HashMap::new(),
-            stored_private_messages: HashMap::new(),
-        }
+    fn nickname_key(&self, peer_id: &PeerId) -> kad::RecordKey {
+        kad::RecordKey::new(&format!(
+            "{}{}",
+            self.nickname_prefix(),
+            peer_id.to_base58()
+        ))
+    }
+
+    fn nickname_prefix(&self) -> String {
+        format!("{}/nickname/", self.hidden(&self.workspace))
     }
 
     pub(crate) async fn run(mut self) {
+        let mut network_info_interval = tokio::time::interval(NETWORK_INFO_INTERVAL);
+        let mut bootstrap_interval = tokio::time::interval(BOOTSTRAP_INTERVAL);
+        let mut republish_interval = tokio::time::interval(self.record_ttl / 2);
+        let mut save_interval = tokio::time::interval(SAVE_INTERVAL);
+
         loop {
             tokio::select! {
                 event = self.swarm.select_next_some() => self.handle_event(event).await,
+                _ = network_info_interval.tick() => {
+                    let status = NetworkStatus {
+                        info: self.network_info(),
+                        room_members: self.room_members(),
+                    };
+                    self.status_sender.send_replace(status);
+                },
+                _ = bootstrap_interval.tick() => {
+                    // Fails until we know at least one peer, which is fine
+                    let _ = self.swarm.behaviour_mut().kademlia.bootstrap();
+                },
+                _ = republish_interval.tick() => self.republish_records(),
+                _ = save_interval.tick() => self.swarm.behaviour_mut().kademlia.store_mut().flush(),
                 command = self.command_receiver.next() => match command {
                     Some(c) => self.handle_command(c).await,
                     // Command channel closed, thus shutting down the network event loop.
@@ -263,6 +1451,25 @@ impl EventLoop {
             SwarmEvent::NewListenAddr { address, .. } => {
                 logger::info!("Node connected to {address}")
             }
+            SwarmEvent::ListenerError { error, .. } => {
+                let mut app = APP.lock().unwrap();
+                app.listen_error(format!("Listener error: {error}"));
+                drop(app);
+            }
+            SwarmEvent::ListenerClosed {
+                addresses,
+                reason: Err(error),
+                ..
+            } => {
+                let addresses: Vec<String> =
+                    addresses.iter().map(|addr| addr.to_string()).collect();
+                let mut app = APP.lock().unwrap();
+                app.listen_error(format!(
+                    "Stopped listening on {}: {error}",
+                    addresses.join(", ")
+                ));
+                drop(app);
+            }
             // Peer discovered
             SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
                 for (peer_id, multiaddr) in list {
@@ -280,10 +1487,8 @@ impl EventLoop {
                         .kademlia
                         .add_address(&peer_id, multiaddr);
 
-                    // Add peer
-                    let mut app = APP.lock().unwrap();
-                    app.add_peer();
-                    drop(app);
+                    // The peer is counted once it has been identified, as peers
+                    // outside of a private network never complete the handshake
                 }
             }
             // Peer expired
@@ -304,14 +1509,114 @@ impl EventLoop {
                         .remove_address(&peer_id, &multiaddr);
 
                     // Decrease count
+                    if self.discovered_peers.remove(&peer_id) {
+                        let mut app = APP.lock().unwrap();
+                        app.remove_peer(peer_id);
+                        drop(app);
+                    }
+                }
+            }
+            // Peer identified, which happens for every peer we complete a handshake with
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+            })) => {
+                logger::info!("Identified peer {peer_id} ({})", info.agent_version);
+
+                for addr in info.listen_addrs {
+                    self.swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, addr);
+                }
+
+                // Relays only help with discovery, they are not peers we can chat with
+                if !info.agent_version.starts_with("swapbytes/") {
+                    return;
+                }
+
+                if self.discovered_peers.insert(peer_id) {
+                    self.swarm
+                        .behaviour_mut()
+                        .gossipsub
+                        .add_explicit_peer(&peer_id);
+
                     let mut app = APP.lock().unwrap();
-                    app.remove_peer(peer_id);
+                    app.add_peer();
                     drop(app);
                 }
             }
+            // Connected to a relay, so look for the peers that are using it
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
+                relay::client::Event::ReservationReqAccepted { relay_peer_id, .. },
+            )) => {
+                logger::info!("Relay reservation accepted by {relay_peer_id}");
+                let _ = self.swarm.behaviour_mut().kademlia.bootstrap();
+            }
+            // Reachability
+            SwarmEvent::Behaviour(BehaviourEvent::Autonat(autonat::Event::StatusChanged {
+                old,
+                new,
+            })) => {
+                logger::info!("NAT status changed from {:?} to {:?}", old, new);
+
+                // Only serve DHT records if other peers can actually reach us
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .set_mode(Some(kademlia_mode(&new)));
+
+                let mut app = APP.lock().unwrap();
+                app.nat_status = new;
+                drop(app);
+            }
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                logger::info!("External address confirmed: {address}");
+                let mut app = APP.lock().unwrap();
+                if !app.external_addresses.contains(&address) {
+                    app.external_addresses.push(address);
+                }
+                drop(app);
+            }
+            SwarmEvent::ExternalAddrExpired { address } => {
+                logger::info!("External address expired: {address}");
+                let mut app = APP.lock().unwrap();
+                app.external_addresses.retain(|addr| *addr != address);
+                drop(app);
+            }
+            // Hole punching
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(dcutr::Event {
+                remote_peer_id,
+                result,
+            })) => match result {
+                Ok(_) => logger::info!("Upgraded to a direct connection with {remote_peer_id}"),
+                Err(e) => logger::info!("Hole punch to {remote_peer_id} failed: {e}"),
+            },
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            } => {
+                let address = endpoint.get_remote_address().clone();
+                self.connections.insert(connection_id, (peer_id, address));
+            }
             // Connection Closed
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                num_established,
+                ..
+            } => {
                 logger::info!("Connection closed to {peer_id}");
+                self.connections.remove(&connection_id);
+
+                // A relayed connection closes once hole punching succeeds, but the peer is still here
+                if num_established > 0 {
+                    return;
+                }
+                self.discovered_peers.remove(&peer_id);
+                self.rate_limiter.remove(&peer_id);
 
                 // Remove from gossipsub
                 self.swarm
@@ -328,15 +1633,78 @@ impl EventLoop {
                 drop(app);
             }
             // Message received
+            // Bring peers that have just joined the workspace up to date
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                peer_id,
+                topic,
+            })) => {
+                if topic == self.registry_topic().hash() {
+                    self.publish_registry();
+                } else if let Some(room) = self.topics.get(&topic).cloned() {
+                    self.share_moderation_log(&room);
+                    if self.pending_history.remove(&room) {
+                        self.send_history_request(&peer_id, &room, HISTORY_SYNC_LIMIT, None);
+                    }
+                }
+            }
             SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
-                propagation_source: peer_id,
-                message_id: _id,
+                propagation_source,
+                message_id,
                 message,
             })) => {
+                // Messages are only relayed to the rest of the room once they have been validated
+                let acceptance = self.validate_message(&propagation_source, &message);
+                let accepted = matches!(acceptance, gossipsub::MessageAcceptance::Accept);
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .report_message_validation_result(&message_id, &propagation_source, acceptance);
+                if !accepted {
+                    return;
+                }
+
+                if message.topic == self.registry_topic().hash() {
+                    if let Some(rooms) = self.parse_registry(&message.data) {
+                        self.merge_rooms(rooms);
+                    }
+                    return;
+                }
+
+                let room = self.topics[&message.topic].clone();
+                let peer_id = message.source.unwrap_or(propagation_source);
+                let chat = match self.decode_message(&room, &message.data) {
+                    Ok(RoomMessage::Chat(signed)) => match self.verify_chat(&room, &signed) {
+                        Ok((_, chat)) => chat,
+                        Err(_) => return,
+                    },
+                    Ok(RoomMessage::Moderation(signed)) => {
+                        self.handle_moderation(&room, signed, true);
+                        return;
+                    }
+                    // Catching up on actions taken before we joined, so they aren't announced
+                    Ok(RoomMessage::ModerationLog(log)) => {
+                        for signed in log {
+                            self.handle_moderation(&room, signed, false);
+                        }
+                        return;
+                    }
+                    Err(_) => return,
+                };
+                let entry = HistoryEntry {
+                    id: chat.id.clone(),
+                    author: peer_id,
+                    data: message.data.clone(),
+                    sent_at: unix_time(),
+                };
+                self.history
+                    .entry(message.topic.clone())
+                    .or_default()
+                    .insert(entry);
+                let (chat_id, message_str) = (chat.id, chat.text);
+
                 let mut app = APP.lock().unwrap();
                 let nicknames = app.nicknames.clone();
-                let topic = message.topic.clone();
-                let message_str = String::from_utf8_lossy(&message.data);
                 logger::info!("Received message from {} : {}", peer_id, message_str);
 
                 // if the nickname is in app.nicknames, then add it
@@ -344,20 +1712,21 @@ impl EventLoop {
 
                 match nicknames.get(&peer_id) {
                     Some(nickname) => {
-                        app.add_message(
-                            MessageType::Message,
-                            format!("{nickname}: {}", message_str),
-                            Some(&topic.into_string()),
-                        );
+                        app.add_room_message(&room, chat_id, peer_id, nickname, &message_str);
+                        // Show the old nickname for now, and pick up any change in the background
+                        if app.nickname_needs_refresh(&peer_id) {
+                            let key = self.nickname_key(&peer_id);
+                            self.swarm.behaviour_mut().kademlia.get_record(key);
+                        }
                     }
                     None => {
                         // Nickname not stored so request it
                         // Need to store the message and wait until kademlia request is fufilled
-                        let key = kad::RecordKey::new(&peer_id.to_bytes());
+                        let key = self.nickname_key(&peer_id);
                         let query_id = self.swarm.behaviour_mut().kademlia.get_record(key);
 
                         self.stored_messages
-                            .insert(query_id.to_string(), message.clone());
+                            .insert(query_id.to_string(), (chat_id, message.clone()));
                     }
                 }
 
@@ -365,6 +1734,16 @@ impl EventLoop {
             }
 
             // Kademlia
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(
+                kad::Event::OutboundQueryProgressed {
+                    result: kad::QueryResult::GetRecord(result),
+                    id,
+                    step,
+                    ..
+                },
+            )) if self.room_info_queries.contains_key(&id) => {
+                self.handle_room_info_query(id, result, step.last)
+            }
             SwarmEvent::Behaviour(BehaviourEvent::Kademlia(
                 kad::Event::OutboundQueryProgressed { result, id, .. },
             )) => match result {
@@ -377,53 +1756,63 @@ impl EventLoop {
                 // Determine if the key is for a room or a nickname
                 {
                     let key_str = String::from_utf8_lossy(key.as_ref());
-
-                    if key_str == "rooms" {
-                        // Deserialize the value as a vector of strings for rooms
-                        match serde_cbor::from_slice::<Vec<String>>(&value) {
-                            Ok(rooms) => {
-                                // Add the rooms to the application state, handle as needed
-                                let mut app = APP.lock().unwrap();
-                                app.rooms = rooms;
-                                drop(app);
+                    let nickname_prefix = self.nickname_prefix();
+
+                    if key == self.rooms_key() {
+                        // Merge rather than replace, as the record may be missing rooms we know about
+                        match self.parse_registry(&value) {
+                            Some(rooms) => self.merge_rooms(rooms),
+                            None => {
+                                logger::error!("Error deserializing rooms");
+                            }
+                        }
+                    } else if let Some(room) = self.room_log_room(&key) {
+                        let value = match self.room_key(&room) {
+                            Some(key) => key.decrypt(&value).unwrap_or_default(),
+                            None => value,
+                        };
+                        // Every action is checked as if it had arrived on the topic
+                        match serde_cbor::from_slice::<Vec<Signed>>(&value) {
+                            Ok(log) => {
+                                for signed in log {
+                                    self.handle_moderation(&room, signed, false);
+                                }
                             }
                             Err(e) => {
-                                logger::error!("Error deserializing rooms: {:?}", e);
+                                logger::error!("Error deserializing moderation log: {:?}", e);
                             }
                         }
-                    } else {
+                    } else if let Some(peer_str) = key_str.strip_prefix(&nickname_prefix) {
                         // Handle nickname retrieval
                         match String::from_utf8(value) {
                             Ok(nickname) => {
-                                match PeerId::from_bytes(key.as_ref()) {
+                                match peer_str.parse::<PeerId>() {
                                     Ok(peer_id) => {
                                         let mut app = APP.lock().unwrap();
-                                        if !app.nicknames.contains_key(&peer_id) {
-                                            logger::info!(
-                                                "Inserted nickname for peer: {:?}",
-                                                key_str
-                                            );
-                                            app.nicknames.insert(peer_id, nickname.clone());
-                                        }
+                                        logger::info!("Inserted nickname for peer: {:?}", key_str);
+                                        app.set_nickname(peer_id, nickname.clone());
                                         drop(app);
 
                                         // If its a message
                                         if self.stored_messages.contains_key(&id.to_string()) {
-                                            if let Some(message) =
+                                            if let Some((chat_id, message)) =
                                                 self.stored_messages.remove(&id.to_string())
                                             {
-                                                let message_str =
-                                                    String::from_utf8_lossy(&message.data);
-                                                let formatted_message =
-                                                    format!("{}: {}", nickname, message_str);
-
-                                                let mut app = APP.lock().unwrap();
-                                                app.add_message(
-                                                    MessageType::Message,
-                                                    formatted_message,
-                                                    Some(&message.topic.into_string()),
-                                                );
-                                                drop(app);
+                                                // The workspace may have changed while waiting
+                                                if let Some(room) = self.topics.get(&message.topic)
+                                                {
+                                                    let message_str =
+                                                        self.chat_text(room, &message.data);
+                                                    let mut app = APP.lock().unwrap();
+                                                    app.add_room_message(
+                                                        room,
+                                                        chat_id,
+                                                        peer_id,
+                                                        &nickname,
+                                                        &message_str,
+                                                    );
+                                                    drop(app);
+                                                }
                                             }
                                         } else if self
                                             .stored_private_messages
@@ -440,7 +1829,7 @@ impl EventLoop {
                                     }
                                     Err(_) => {
                                         logger::error!(
-                                            "Unable to get peerId from bytes: {:?}",
+                                            "Unable to get peerId from key: {:?}",
                                             key_str
                                         );
                                     }
@@ -455,16 +1844,105 @@ impl EventLoop {
                 kad::QueryResult::GetRecord(Ok(_)) => {}
                 kad::QueryResult::GetRecord(Err(err)) => {
                     logger::error!("Failed to get record: {:?}", err);
+
+                    // The nickname record has expired or was never found, so show a short peer id instead
+                    let peer_id = match self.nickname_peer(err.key()) {
+                        Some(peer_id) => peer_id,
+                        None => return,
+                    };
+                    if let Some((chat_id, message)) = self.stored_messages.remove(&id.to_string()) {
+                        if let Some(room) = self.topics.get(&message.topic) {
+                            let message_str = self.chat_text(room, &message.data);
+                            let mut app = APP.lock().unwrap();
+                            let name = app.display_name(&peer_id);
+                            app.add_room_message(room, chat_id, peer_id, &name, &message_str);
+                            drop(app);
+                        }
+                    } else if let Some(request) =
+                        self.stored_private_messages.remove(&id.to_string())
+                    {
+                        if request.request_type == RequestType::Join {
+                            let mut app = APP.lock().unwrap();
+                            let shown = show_invite(&mut app, peer_id);
+                            drop(app);
+                            if !shown {
+                                self.reject_busy(peer_id);
+                            }
+                        }
+                    }
                 }
                 kad::QueryResult::PutRecord(Ok(kad::PutRecordOk { key })) => {
                     logger::info!("Successfully put record for {:?}", key);
                 }
+                // The record is still stored on the peers that were reached, and is retried when republished
+                kad::QueryResult::PutRecord(Err(kad::PutRecordError::QuorumFailed {
+                    key,
+                    success,
+                    ..
+                })) => {
+                    logger::info!("Record {:?} only stored on {} peers", key, success.len());
+                }
                 kad::QueryResult::PutRecord(Err(err)) => {
                     logger::error!("Failed to put record: {:?}", err);
                 }
                 _ => (),
             },
 
+            // Room invitations
+            SwarmEvent::Behaviour(BehaviourEvent::Rooms(request_response::Event::Message {
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+                peer,
+            })) => {
+                if self.blocked_peers.contains(&peer) {
+                    logger::info!("Dropped room request from blocked peer {peer}");
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .rooms
+                        .send_response(channel, RoomResponse::Ack);
+                    return;
+                }
+
+                let response = match request {
+                    RoomRequest::Invite(signed) => {
+                        self.handle_invite(signed, peer);
+                        RoomResponse::Ack
+                    }
+                    RoomRequest::History {
+                        topic,
+                        limit,
+                        since,
+                    } => RoomResponse::History(self.room_history(topic, limit, since)),
+                };
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .rooms
+                    .send_response(channel, response);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Rooms(request_response::Event::Message {
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+                peer,
+            })) => {
+                if let RoomResponse::History(entries) = response {
+                    self.handle_history(request_id, peer, entries);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Rooms(
+                request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                },
+            )) => {
+                logger::error!("Room request failed: {:?}", error);
+                self.history_requests.remove(&request_id);
+            }
             // Private Messaging
             SwarmEvent::Behaviour(BehaviourEvent::RequestResponse(
                 request_response::Event::Message { message, peer },
@@ -472,6 +1950,12 @@ impl EventLoop {
                 request_response::Message::Request {
                     request, channel, ..
                 } => {
+                    // Drop invites and messages from blocked peers
+                    if self.blocked_peers.contains(&peer) {
+                        logger::info!("Dropped request from blocked peer {peer}");
+                        return;
+                    }
+
                     // If we already have a requested_file and we recieve a file request, just send a file response
                     let app = APP.lock().unwrap();
                     let requested_file = app.requested_file.clone();
@@ -493,28 +1977,229 @@ impl EventLoop {
 
                     self.send_response(path.is_none(), path, channel);
                 }
-                request_response::Message::Response { response, .. } => {
-                    self.handle_private_response(response);
+                request_response::Message::Response { response, .. } => {
+                    self.handle_private_response(response);
+                }
+            },
+
+            unhandled => logger::error!("Unhandled event: {:?}", unhandled),
+        }
+    }
+
+    async fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::StartListening { addr, sender } => {
+                let _ = match self.swarm.listen_on(addr) {
+                    Ok(_) => sender.send(Ok(())),
+                    Err(e) => sender.send(Err(Box::new(e))),
+                };
+            }
+            Command::ChangeTopic { topic, sender } => {
+                if self
+                    .moderation_state(&topic)
+                    .is_banned(self.swarm.local_peer_id())
+                {
+                    let _ = sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        format!("You are banned from {topic}"),
+                    ))));
+                    return;
+                }
+                let scoped_topic = self.scoped_topic(&topic);
+                let _ = match self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .subscribe(&scoped_topic)
+                {
+                    Ok(subscribed) => {
+                        let _ = self.swarm.behaviour_mut().gossipsub.set_topic_params(
+                            scoped_topic.clone(),
+                            validation::topic_score_params(),
+                        );
+                        self.topics.insert(scoped_topic.hash(), topic.clone());
+
+                        // Catch up on what was said before we joined
+                        if subscribed && !self.room_keys.contains_key(&topic) {
+                            let key = self.room_log_key(&topic);
+                            self.swarm.behaviour_mut().kademlia.get_record(key);
+                        }
+                        if subscribed && self.request_history(&topic, HISTORY_SYNC_LIMIT, None) == 0
+                        {
+                            self.pending_history.insert(topic);
+                        }
+                        sender.send(Ok(()))
+                    }
+                    Err(e) => sender.send(Err(Box::new(e))),
+                };
+            }
+            Command::LeaveTopic { topic, sender } => {
+                let scoped_topic = self.scoped_topic(&topic);
+                let _ = match self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .unsubscribe(&scoped_topic)
+                {
+                    Ok(_) => {
+                        self.topics.remove(&scoped_topic.hash());
+                        self.history.remove(&scoped_topic.hash());
+                        self.pending_history.remove(&topic);
+                        // Leaving a private room gives up its key
+                        if self.room_keys.remove(&topic).is_some() {
+                            let key = self.private_info_key(&topic);
+                            self.own_records.remove(&key);
+                        }
+                        self.private_owners.remove(&topic);
+                        sender.send(Ok(()))
+                    }
+                    Err(e) => sender.send(Err(Box::new(e))),
+                };
+            }
+            Command::ChangeWorkspace { workspace, sender } => {
+                logger::info!("Changing workspace to {}", workspace);
+
+                // Leave every room in the old workspace
+                let rooms: Vec<String> = self.topics.drain().map(|(_, room)| room).collect();
+                for room in rooms {
+                    let scoped_topic = self.scoped_topic(&room);
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .gossipsub
+                        .unsubscribe(&scoped_topic);
+                }
+
+                // Anything still waiting on a nickname belongs to the old workspace
+                self.stored_messages.clear();
+                self.stored_private_messages.clear();
+
+                // Stop republishing records from the old workspace
+                for key in self.own_records.keys() {
+                    self.swarm.behaviour_mut().kademlia.remove_record(key);
                 }
-            },
+                self.own_records.clear();
 
-            unhandled => logger::error!("Unhandled event: {:?}", unhandled),
-        }
-    }
+                let registry_topic = self.registry_topic();
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .unsubscribe(&registry_topic);
+
+                self.workspace = workspace;
+                self.rooms = RoomRegistry::default();
+                self.room_keys.clear();
+                self.private_owners.clear();
+                self.history.clear();
+                self.history_requests.clear();
+                self.pending_history.clear();
+                self.subscribe_registry();
+                self.load_moderation();
+                let _ = sender.send(Ok(()));
+            }
+            Command::InviteAddresses { sender } => {
+                let _ = sender.send(Ok(self.invite_addresses()));
+            }
+            Command::Dial { addresses, sender } => {
+                let local_peer_id = *self.swarm.local_peer_id();
+                let mut peers = 0;
+                let mut dialled = 0;
+                for address in addresses {
+                    let peer_id = match address.iter().last() {
+                        Some(Protocol::P2p(peer_id)) => peer_id,
+                        _ => continue,
+                    };
+                    if peer_id == local_peer_id || self.blocked_peers.contains(&peer_id) {
+                        continue;
+                    }
+                    peers += 1;
 
-    async fn handle_command(&mut self, command: Command) {
-        match command {
-            Command::StartListening { addr, sender } => {
-                let _ = match self.swarm.listen_on(addr) {
-                    Ok(_) => sender.send(Ok(())),
-                    Err(e) => sender.send(Err(Box::new(e))),
+                    self.swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, address.clone());
+                    match self.swarm.dial(address.clone()) {
+                        Ok(_) => dialled += 1,
+                        Err(e) => logger::error!("Unable to dial {address}: {e}"),
+                    }
+                }
+
+                // Whoever answers knows the rest of the workspace
+                let _ = self.swarm.behaviour_mut().kademlia.bootstrap();
+                let _ = match peers > 0 && dialled == 0 {
+                    true => sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::ConnectionRefused,
+                        "Unable to dial any peer in the link",
+                    )))),
+                    false => sender.send(Ok(())),
                 };
             }
-            Command::ChangeTopic { topic, sender } => {
-                let topic = IdentTopic::new(topic.clone());
-                let _ = match self.swarm.behaviour_mut().gossipsub.subscribe(&topic) {
+            Command::AddRoomKey {
+                room,
+                key,
+                owner,
+                sender,
+            } => {
+                if let Some(owner) = owner {
+                    self.private_owners.insert(room.clone(), owner);
+                }
+                self.room_keys.insert(room, key);
+                let _ = sender.send(Ok(()));
+            }
+            Command::PublishRoomInfo { room, info, sender } => {
+                let key = match self.room_keys.get(&room) {
+                    Some(key) => key.clone(),
+                    None => {
+                        let _ = sender.send(Err(Box::new(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("No key for {room}"),
+                        ))));
+                        return;
+                    }
+                };
+                let result = serde_cbor::to_vec(&info)
+                    .map_err(|e| format!("{:?}", e))
+                    .and_then(|bytes| {
+                        let record_key = self.private_info_key(&room);
+                        self.put_record(record_key, key.encrypt(&bytes))
+                            .map_err(|e| format!("{:?}", e))
+                    });
+                let _ = match result {
                     Ok(_) => sender.send(Ok(())),
-                    Err(e) => sender.send(Err(Box::new(e))),
+                    Err(e) => sender.send(Err(Box::new(std::io::Error::other(e)))),
+                };
+            }
+            Command::FetchRoomInfo { room, key, sender } => {
+                let record_key = self.private_info_key(&room);
+                let id = self.swarm.behaviour_mut().kademlia.get_record(record_key);
+                self.room_info_queries.insert(
+                    id,
+                    RoomInfoQuery {
+                        room,
+                        key,
+                        wrong_key: false,
+                        sender,
+                    },
+                );
+            }
+            Command::SendInvite {
+                peer_id,
+                invitation,
+                sender,
+            } => {
+                let _ = match Signed::sign(&self.keypair, &invitation) {
+                    Some(signed) => {
+                        self.swarm
+                            .behaviour_mut()
+                            .rooms
+                            .send_request(&peer_id, RoomRequest::Invite(signed));
+                        sender.send(Ok(()))
+                    }
+                    None => sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Unable to sign invitation",
+                    )))),
                 };
             }
             Command::SendTopicMessage {
@@ -522,72 +2207,232 @@ impl EventLoop {
                 topic,
                 sender,
             } => {
-                let _ = match self
-                    .swarm
-                    .behaviour_mut()
-                    .gossipsub
-                    .publish(topic, message.as_bytes())
+                let room = topic.to_string();
+
+                // Every other peer would drop it, so don't send it
+                let local_peer_id = *self.swarm.local_peer_id();
+                if let Some(until) = self
+                    .moderation_state(&room)
+                    .muted_until(&local_peer_id, unix_time())
                 {
-                    Ok(_) => sender.send(Ok(())),
-                    Err(e) => sender.send(Err(Box::new(e))),
+                    let _ = sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        format!(
+                            "You are muted in {room} for another {} minute(s)",
+                            minutes_until(until)
+                        ),
+                    ))));
+                    return;
+                }
+                if !self.can_post(&room, &local_peer_id) {
+                    let _ = sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        format!("Only publishers can post in {room}"),
+                    ))));
+                    return;
+                }
+
+                // Signed so peers we pass it on to in history can tell who wrote it
+                let id = format!("{local_peer_id}{:016x}", rand::random::<u64>());
+                let chat = ChatMessage {
+                    id: id.clone(),
+                    workspace: self.workspace.clone(),
+                    room: room.clone(),
+                    text: message,
+                    sent_at: unix_time(),
+                };
+                let signed = match Signed::sign(&self.keypair, &chat) {
+                    Some(signed) => signed,
+                    None => {
+                        let _ = sender.send(Err(Box::new(std::io::Error::other(
+                            "Unable to sign the message",
+                        ))));
+                        return;
+                    }
+                };
+                let _ = match self.publish_to_room(&room, &RoomMessage::Chat(signed)) {
+                    Ok(_) => sender.send(Ok(id)),
+                    Err(e) => sender.send(Err(e)),
+                };
+            }
+            Command::RequestHistory {
+                room,
+                limit,
+                since,
+                sender,
+            } => {
+                let _ = match self.request_history(&room, limit, since) {
+                    0 => sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("No other peers in {room} to ask for its history"),
+                    )))),
+                    _ => sender.send(Ok(())),
+                };
+            }
+            Command::Moderate {
+                room,
+                action,
+                sender,
+            } => {
+                // Pins only hold the message id, so everyone reads the text the author sent
+                if let Action::Pin(message_id) = &action {
+                    let known = self
+                        .history
+                        .get(&self.scoped_topic(&room).hash())
+                        .is_some_and(|history| history.get(message_id).is_some());
+                    if !known {
+                        let _ = sender.send(Err(Box::new(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("That message is no longer in the history of {room}"),
+                        ))));
+                        return;
+                    }
+                }
+
+                let moderation = Moderation {
+                    workspace: self.workspace.clone(),
+                    room: room.clone(),
+                    action,
+                    issued_at: unix_time(),
                 };
+                let signed = match Signed::sign(&self.keypair, &moderation) {
+                    Some(signed) => signed,
+                    None => {
+                        let _ = sender.send(Err(Box::new(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "Unable to sign moderation action",
+                        ))));
+                        return;
+                    }
+                };
+
+                // The UI shows the action itself, as it is waiting on this reply
+                if self.apply_moderation(&room, signed.clone()).is_none() {
+                    let _ = sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        format!("You can't do that in {room}"),
+                    ))));
+                    return;
+                }
+
+                // It has already been applied here, and peers that miss it get the log when they join
+                if let Err(e) = self.publish_to_room(&room, &RoomMessage::Moderation(signed)) {
+                    logger::error!("Unable to publish moderation action: {:?}", e);
+                }
+                self.store_room_log(&room);
+                let _ = sender.send(Ok(()));
+            }
+            Command::RoomDetails { room, sender } => {
+                let details = self.room_details(&room);
+                let _ = sender.send(Ok(details));
             }
             Command::AddNickname {
                 nickname,
                 peer_id,
                 sender,
             } => {
-                let record = kad::Record {
-                    key: kad::RecordKey::new(&peer_id.to_bytes()),
-                    value: nickname.clone().into_bytes(),
-                    publisher: None,
-                    expires: None,
-                };
                 logger::info!(
                     "Creating new record with peer_id: {:?} and nickname: {:?}",
                     peer_id.to_base58(),
                     nickname.clone()
                 );
-                let _ = match self
-                    .swarm
-                    .behaviour_mut()
-                    .kademlia
-                    .put_record(record, kad::Quorum::One)
-                {
+                let key = self.nickname_key(&peer_id);
+                let _ = match self.put_record(key, nickname.clone().into_bytes()) {
                     Ok(_) => sender.send(Ok(())),
                     Err(e) => sender.send(Err(Box::new(e))),
                 };
             }
 
-            Command::AddRoom { rooms, sender } => {
-                logger::info!("Adding rooms {:?}", rooms);
+            Command::AddRoom {
+                room,
+                mut info,
+                sender,
+            } => {
+                logger::info!("Adding room {:?}", room);
+                if self.rooms.is_full() {
+                    let _ = sender.send(Err(Box::new(std::io::Error::other(format!(
+                        "{} already has the most rooms a workspace can have ({MAX_ROOMS})",
+                        self.workspace
+                    )))));
+                    return;
+                }
+                if self.rooms.is_full_for(Some(*self.swarm.local_peer_id())) {
+                    let _ = sender.send(Err(Box::new(std::io::Error::other(format!(
+                        "You already have the most rooms one peer can create ({MAX_ROOMS_PER_CREATOR})"
+                    )))));
+                    return;
+                }
 
-                let rooms_bytes = match serde_cbor::to_vec(&rooms) {
-                    Ok(room_bytes) => room_bytes,
-                    Err(e) => {
-                        sender.send(Err(Box::new(e))).unwrap();
-                        return;
+                if !info.sign(&self.keypair, &self.workspace, &room) {
+                    let _ = sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Unable to sign room",
+                    ))));
+                    return;
+                }
+                let _ = match self.rooms.insert(room, info.clone()) {
+                    true => {
+                        self.publish_registry();
+                        self.store_registry();
+                        sender.send(Ok(info))
                     }
+                    false => sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        "Room already exists",
+                    )))),
                 };
-                let record = kad::Record {
-                    key: kad::RecordKey::new(&"rooms".to_string()),
-                    value: rooms_bytes,
-                    publisher: None,
-                    expires: None,
-                };
-                let _ = match self
-                    .swarm
-                    .behaviour_mut()
-                    .kademlia
-                    .put_record(record, kad::Quorum::One)
-                {
-                    Ok(_) => sender.send(Ok(())),
-                    Err(e) => sender.send(Err(Box::new(e))),
+            }
+            Command::SignRoom {
+                room,
+                mut info,
+                sender,
+            } => {
+                let _ = match info.sign(&self.keypair, &self.workspace, &room) {
+                    true => sender.send(Ok(info)),
+                    false => sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "Unable to sign room",
+                    )))),
                 };
             }
 
+            Command::SetRoomNames {
+                room,
+                mut names,
+                sender,
+            } => {
+                // Only the creator's signature is accepted by other peers
+                let local_peer_id = *self.swarm.local_peer_id();
+                let creator = self.rooms.get(&room).and_then(|info| info.creator);
+                if creator != Some(local_peer_id) || self.room_keys.contains_key(&room) {
+                    let _ = sender.send(Err(Box::new(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        format!("Only the owner of {room} can rename it"),
+                    ))));
+                    return;
+                }
+
+                names.workspace = self.workspace.clone();
+                names.room = room.clone();
+                names.issued_at = unix_time();
+                let signed = match Signed::sign(&self.keypair, &names) {
+                    Some(signed) => signed,
+                    None => {
+                        let _ = sender.send(Err(Box::new(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "Unable to sign room names",
+                        ))));
+                        return;
+                    }
+                };
+                if self.rooms.set_names(&room, signed) {
+                    self.publish_registry();
+                    self.store_registry();
+                }
+                let _ = sender.send(Ok(()));
+            }
             Command::FetchRooms { sender } => {
-                let key = kad::RecordKey::new(&"rooms");
+                let key = self.rooms_key();
                 self.swarm.behaviour_mut().kademlia.get_record(key);
                 let _ = sender.send(Ok(()));
             }
@@ -619,9 +2464,321 @@ impl EventLoop {
 
                 let _ = sender.send(Ok(()));
             }
+
+            Command::BlockPeer { peer_id, sender } => {
+                logger::info!("Blocking peer {peer_id}");
+
+                // Closes every connection to the peer and refuses new ones
+                self.swarm.behaviour_mut().blocked_peers.block_peer(peer_id);
+                self.blocked_peers.insert(peer_id);
+
+                let _ = match self.save_blocked_peers() {
+                    Ok(_) => sender.send(Ok(())),
+                    Err(e) => sender.send(Err(Box::new(e))),
+                };
+            }
+
+            Command::UnblockPeer { peer_id, sender } => {
+                logger::info!("Unblocking peer {peer_id}");
+
+                self.swarm
+                    .behaviour_mut()
+                    .blocked_peers
+                    .unblock_peer(peer_id);
+                self.blocked_peers.remove(&peer_id);
+
+                let _ = match self.save_blocked_peers() {
+                    Ok(_) => sender.send(Ok(())),
+                    Err(e) => sender.send(Err(Box::new(e))),
+                };
+            }
+        }
+    }
+
+    fn handle_invite(&mut self, signed: Signed, peer: PeerId) {
+        // Only accept invitations signed by the peer that sent them
+        let invitation: Invitation = match signed.verify() {
+            Some((signer, invitation)) if signer == peer => invitation,
+            _ => {
+                logger::info!("Dropped invitation with a bad signature from {peer}");
+                return;
+            }
+        };
+        if invitation.workspace != self.workspace {
+            logger::info!("Dropped invitation for another workspace from {peer}");
+            return;
+        }
+
+        let mut app = APP.lock().unwrap();
+        let name = app.display_name(&peer);
+        let topic = app.topic.to_string();
+        app.add_message(
+            MessageType::Info,
+            format!(
+                "{name} has invited you to the private room {}. Type \"/join {}\" to join",
+                invitation.room, invitation.room
+            ),
+            Some(&topic),
+        );
+        app.pending_invites
+            .insert(invitation.room.clone(), invitation);
+        drop(app);
+    }
+
+    // Count the peers subscribed to each room, including ourselves
+    fn room_members(&self) -> HashMap<String, usize> {
+        let mut subscribers: HashMap<TopicHash, usize> = HashMap::new();
+        for (_, topics) in self.swarm.behaviour().gossipsub.all_peers() {
+            for topic in topics {
+                *subscribers.entry(topic.clone()).or_default() += 1;
+            }
+        }
+
+        // Private rooms aren't in the registry but still show a member count
+        self.rooms
+            .infos()
+            .map(|(room, _)| room)
+            .chain(self.room_keys.keys())
+            .map(|room| {
+                let hash = self.scoped_topic(room).hash();
+                let us = usize::from(self.topics.contains_key(&hash));
+                let members = subscribers.get(&hash).copied().unwrap_or(0) + us;
+                (room.clone(), members)
+            })
+            .collect()
+    }
+
+    // Confirmed external addresses first, then relayed ones, then the rest of our listeners
+    fn invite_addresses(&self) -> Vec<Multiaddr> {
+        let local_peer_id = *self.swarm.local_peer_id();
+        let rank = |address: &Multiaddr| {
+            if address
+                .iter()
+                .any(|protocol| protocol == Protocol::P2pCircuit)
+            {
+                1
+            } else if address.iter().any(|protocol| match protocol {
+                Protocol::Ip4(ip) => ip.is_loopback(),
+                Protocol::Ip6(ip) => ip.is_loopback(),
+                _ => false,
+            }) {
+                3
+            } else {
+                2
+            }
+        };
+        let mut listeners: Vec<&Multiaddr> = self.swarm.listeners().collect();
+        listeners.sort_by_key(|address| rank(address));
+
+        let mut addresses: Vec<Multiaddr> = vec![];
+        for address in self.swarm.external_addresses().chain(listeners) {
+            let address = match address.iter().last() {
+                Some(Protocol::P2p(_)) => address.clone(),
+                _ => address.clone().with(Protocol::P2p(local_peer_id)),
+            };
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+        addresses.truncate(INVITE_ADDRESSES);
+        addresses
+    }
+
+    // Collect everything shown in the diagnostics tab
+    fn network_info(&mut self) -> NetworkInfo {
+        let mut connections: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+        for (peer_id, address) in self.connections.values() {
+            connections
+                .entry(*peer_id)
+                .or_default()
+                .push(address.clone());
+        }
+        let mut connections: Vec<(PeerId, Vec<Multiaddr>)> = connections.into_iter().collect();
+        connections.sort_by_key(|(peer_id, _)| peer_id.to_base58());
+
+        let buckets = self
+            .swarm
+            .behaviour_mut()
+            .kademlia
+            .kbuckets()
+            .filter(|bucket| bucket.num_entries() > 0)
+            .filter_map(|bucket| {
+                let index = bucket.range().0.ilog2()?;
+                Some((index, bucket.num_entries()))
+            })
+            .collect();
+
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let mut room_peers: Vec<(String, Vec<PeerId>)> = self
+            .topics
+            .iter()
+            .map(|(hash, room)| {
+                let peers = gossipsub
+                    .all_peers()
+                    .filter(|(_, topics)| topics.contains(&hash))
+                    .map(|(peer_id, _)| *peer_id);
+                (room.clone(), peers.collect())
+            })
+            .collect();
+        room_peers.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let pending_messages = self
+            .stored_messages
+            .iter()
+            .map(|(query_id, (_, message))| {
+                let room = self.topics.get(&message.topic).cloned();
+                (query_id.clone(), room.unwrap_or(message.topic.to_string()))
+            })
+            .collect();
+        let pending_private_messages = self
+            .stored_private_messages
+            .iter()
+            .map(|(query_id, request)| (query_id.clone(), format!("{:?}", request.request_type)))
+            .collect();
+
+        NetworkInfo {
+            peer_id: Some(*self.swarm.local_peer_id()),
+            listen_addrs: self.swarm.listeners().cloned().collect(),
+            external_addrs: self.swarm.external_addresses().cloned().collect(),
+            connections,
+            buckets,
+            room_peers,
+            pending_messages,
+            pending_private_messages,
+        }
+    }
+
+    // Decide whether a room message should be shown and relayed to the rest of the room
+    fn validate_message(
+        &mut self,
+        propagation_source: &PeerId,
+        message: &gossipsub::Message,
+    ) -> gossipsub::MessageAcceptance {
+        let author = message.source.unwrap_or(*propagation_source);
+
+        // Drop anything written by a blocked peer, even when relayed through others
+        if self.blocked_peers.contains(&author) {
+            logger::info!("Dropped message from blocked peer {author}");
+            return gossipsub::MessageAcceptance::Ignore;
+        }
+
+        // Registry updates are rate limited like any other message
+        if message.topic == self.registry_topic().hash() {
+            if self.parse_registry(&message.data).is_none() {
+                logger::info!("Rejected room registry from {author}");
+                return gossipsub::MessageAcceptance::Reject;
+            }
+            return self.check_rate(author, propagation_source, None);
+        }
+
+        // Ignore messages for rooms outside of our workspace
+        let room = match self.topics.get(&message.topic) {
+            Some(room) => room.clone(),
+            None => return gossipsub::MessageAcceptance::Ignore,
+        };
+
+        let room_message = match self.decode_message(&room, &message.data) {
+            Ok(room_message) => room_message,
+            Err(e) => {
+                logger::info!("Rejected message from {author}: {:?}", e);
+                return gossipsub::MessageAcceptance::Reject;
+            }
+        };
+
+        match &room_message {
+            RoomMessage::Chat(signed) => {
+                // Whoever signed it must be the one who published it
+                let chat = match signed.verify::<ChatMessage>() {
+                    Some((signer, chat)) if signer == author => chat,
+                    _ => {
+                        logger::info!("Rejected chat message from {author} signed by someone else");
+                        return gossipsub::MessageAcceptance::Reject;
+                    }
+                };
+                // Stop relaying anything a moderator has removed, or that comes from a banned or muted peer
+                let state = self.moderation_state(&room);
+                if state.is_banned(&author)
+                    || state.muted_until(&author, unix_time()).is_some()
+                    || state.is_deleted(&chat.id)
+                {
+                    logger::info!("Dropped message from {author} removed by a moderator of {room}");
+                    return gossipsub::MessageAcceptance::Ignore;
+                }
+                // Publishers come from the owner's signed moderation log, which is sent to
+                // everyone joining, so anyone else posting is rejected
+                if !self.can_post(&room, &author) {
+                    logger::info!("Rejected message from {author}, who can't post in {room}");
+                    return gossipsub::MessageAcceptance::Reject;
+                }
+            }
+            // Whether the signer is allowed to take the action is checked once it is applied
+            RoomMessage::Moderation(signed) => {
+                if signed.verify::<Moderation>().is_none() {
+                    return gossipsub::MessageAcceptance::Reject;
+                }
+            }
+            RoomMessage::ModerationLog(log) => {
+                if log
+                    .iter()
+                    .any(|signed| signed.verify::<Moderation>().is_none())
+                {
+                    return gossipsub::MessageAcceptance::Reject;
+                }
+            }
+        }
+
+        self.check_rate(author, propagation_source, Some(&room))
+    }
+
+    // Accept a message unless its author is sending too many
+    fn check_rate(
+        &mut self,
+        author: PeerId,
+        propagation_source: &PeerId,
+        room: Option<&String>,
+    ) -> gossipsub::MessageAcceptance {
+        // Only penalise the peer that is actually sending too much, not peers relaying for them
+        let over_limit = match author == *propagation_source {
+            true => gossipsub::MessageAcceptance::Reject,
+            false => gossipsub::MessageAcceptance::Ignore,
+        };
+
+        match self.rate_limiter.check(author) {
+            RateLimit::Allowed => gossipsub::MessageAcceptance::Accept,
+            RateLimit::Throttled => {
+                logger::info!("Throttling messages from {author}");
+                let mut app = APP.lock().unwrap();
+                let name = app.display_name(&author);
+                // Registry updates aren't from any room, so say so in the one being viewed
+                let room = room.cloned().unwrap_or_else(|| app.topic.to_string());
+                app.add_message(
+                    MessageType::Error,
+                    format!(
+                        "Messages from {name} are being throttled as they are sending too many"
+                    ),
+                    Some(&room),
+                );
+                drop(app);
+                over_limit
+            }
+            RateLimit::StillThrottled => over_limit,
         }
     }
 
+    // Let a peer know we can't take their invite right now, rather than leave them waiting
+    fn reject_busy(&mut self, peer: PeerId) {
+        logger::info!("Turning down an invite from {peer} while busy with another peer");
+        let request = PrivateRequest {
+            request_type: RequestType::Reject,
+            message: None,
+            filename: None,
+        };
+        self.swarm
+            .behaviour_mut()
+            .request_response
+            .send_request(&peer, request);
+    }
+
     fn handle_private_request(&mut self, request: PrivateRequest, peer: PeerId) {
         let mut app = APP.lock().unwrap();
         logger::info!(
@@ -632,41 +2789,32 @@ impl EventLoop {
             RequestType::Join => {
                 // Show the user that someone wants to connect
                 // Get the nickname and display a message to the user
-                // If the user already has someone trying to connect, return
-                match app.connected_peer {
-                    Some(_) => {
-                        drop(app);
-                        return;
-                    }
-                    _ => {}
-                };
-
-                let nicknames = app.nicknames.clone();
-                let nickname = match nicknames.get(&peer) {
-                    Some(nickname) => nickname,
-                    None => {
-                        // Handle getting the nickname from kademlia
-                        let key = kad::RecordKey::new(&peer.to_bytes());
-                        let query_id = self.swarm.behaviour_mut().kademlia.get_record(key);
+                // If the user already has someone else trying to connect, turn them down
+                if app
+                    .connected_peer
+                    .is_some_and(|connected_peer| connected_peer != peer)
+                {
+                    drop(app);
+                    self.reject_busy(peer);
+                    return;
+                }
 
-                        self.stored_private_messages
-                            .insert(query_id.to_string(), request);
-                        drop(app);
-                        return;
-                    }
-                };
-                let topic = app.topic.clone();
+                if !app.nicknames.contains_key(&peer) {
+                    // Handle getting the nickname from kademlia
+                    let key = self.nickname_key(&peer);
+                    let query_id = self.swarm.behaviour_mut().kademlia.get_record(key);
 
-                app.connected_peer = Some(peer.clone());
+                    self.stored_private_messages
+                        .insert(query_id.to_string(), request);
+                    drop(app);
+                    return;
+                }
 
-                app.add_message(
-                    MessageType::Info,
-                    format!(
-                        "{} has invited you to chat! Type \"/accept\" to accept or \"/reject\" to reject",
-                        nickname
-                    ),
-                    Some(&topic.to_string()),
-                )
+                if !show_invite(&mut app, peer) {
+                    drop(app);
+                    self.reject_busy(peer);
+                    return;
+                }
             }
             RequestType::Accept => {
                 if app.requesting_file {
@@ -687,12 +2835,12 @@ impl EventLoop {
                 }
             }
             RequestType::Reject => {
-                // Peer has rejected
-                let nicknames = app.nicknames.clone();
-                let default_nickname = "Peer".to_string();
-                let nickname = nicknames
-                    .get(&app.connected_peer.unwrap())
-                    .unwrap_or(&default_nickname);
+                // Only the peer we asked can turn us down
+                if app.connected_peer != Some(peer) {
+                    drop(app);
+                    return;
+                }
+                let nickname = app.display_name(&peer);
 
                 let topic_str = app.topic.clone().to_string();
                 let topic = match app.connected {
@@ -715,8 +2863,7 @@ impl EventLoop {
             RequestType::Message => {
                 // Display the message to the user
                 let peer_id = app.connected_peer.clone().unwrap();
-                let nicknames = app.nicknames.clone();
-                let nickname = nicknames.get(&peer_id).unwrap();
+                let nickname = app.display_name(&peer_id);
                 app.add_message(
                     MessageType::Message,
                     format!(
@@ -729,8 +2876,7 @@ impl EventLoop {
             }
             RequestType::FileRequest => {
                 let peer_id = app.connected_peer.clone().unwrap();
-                let nicknames = app.nicknames.clone();
-                let nickname = nicknames.get(&peer_id).unwrap();
+                let nickname = app.display_name(&peer_id);
                 let requested_file = request.filename.unwrap();
 
                 app.add_message(
@@ -748,8 +2894,7 @@ impl EventLoop {
             RequestType::Leave => {
                 // Show a message saying that the user has left
                 let peer_id = app.connected_peer.clone().unwrap();
-                let nicknames = app.nicknames.clone();
-                let nickname = nicknames.get(&peer_id).unwrap();
+                let nickname = app.display_name(&peer_id);
                 let topic = app.topic.clone();
                 app.add_message(
                     MessageType::Info,
@@ -842,6 +2987,37 @@ impl EventLoop {
     }
 }
 
+// Show an invite to chat privately, unless another invite is already waiting
+// Returns false if we are busy with another peer, so the inviter can be told
+fn show_invite(app: &mut App, peer: PeerId) -> bool {
+    match app.connected_peer {
+        // Asking again, so show it again
+        Some(connected_peer) if connected_peer == peer && !app.connected => {}
+        Some(_) => return false,
+        None => app.connected_peer = Some(peer),
+    }
+
+    let nickname = app.display_name(&peer);
+    let topic = app.topic.clone();
+    app.add_message(
+        MessageType::Info,
+        format!(
+            "{} has invited you to chat! Type \"/accept\" to accept or \"/reject\" to reject",
+            nickname
+        ),
+        Some(&topic.to_string()),
+    );
+    true
+}
+
+// Peers that are not publicly reachable act as DHT clients so others don't try to query them
+fn kademlia_mode(nat_status: &autonat::NatStatus) -> Mode {
+    match nat_status {
+        autonat::NatStatus::Private => Mode::Client,
+        autonat::NatStatus::Public(_) | autonat::NatStatus::Unknown => Mode::Server,
+    }
+}
+
 // Structs for sending DMs and potentially files
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -0,0 +1,106 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+// Slows down guessing a room password from captured messages
+const PASSWORD_ROUNDS: u32 = 100_000;
+const NONCE_LENGTH: usize = 24;
+
+/// Symmetric key shared by every member of a private room
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomKey([u8; 32]);
+
+impl fmt::Debug for RoomKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RoomKey({})", self.topic_id())
+    }
+}
+
+impl RoomKey {
+    /// Random key for an invite-only room
+    pub fn generate() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    /// Key for a password protected room, the same for everyone who knows the password
+    pub fn from_password(workspace: &str, room: &str, password: &str) -> Self {
+        let salt = format!("swapbytes/{workspace}/{room}");
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(
+            password.as_bytes(),
+            salt.as_bytes(),
+            PASSWORD_ROUNDS,
+            &mut key,
+        );
+        Self(key)
+    }
+
+    /// Key anyone who knows a name can derive, hiding it from everyone who doesn't
+    pub fn from_name(name: &str) -> Self {
+        let hash = Sha256::new()
+            .chain_update(b"swapbytes/name")
+            .chain_update(name.as_bytes())
+            .finalize();
+        Self(hash.into())
+    }
+
+    /// Identifies the room's topic without revealing its name or key
+    pub fn topic_id(&self) -> String {
+        let hash = Sha256::new()
+            .chain_update(b"swapbytes/topic")
+            .chain_update(self.0)
+            .finalize();
+        hash[..16]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    /// Encrypt a message, prefixing it with a random nonce
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let cipher = XChaCha20Poly1305::new(&self.0.into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut sealed = nonce.to_vec();
+        // Encrypting into a Vec can't fail
+        sealed.extend(cipher.encrypt(&nonce, plaintext).unwrap_or_default());
+        sealed
+    }
+
+    /// Decrypt a message from `encrypt`, failing if it was written with another key or tampered with
+    pub fn decrypt(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LENGTH {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        let cipher = XChaCha20Poly1305::new(&self.0.into());
+        cipher.decrypt(XNonce::from_slice(nonce), ciphertext).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_room_key_decrypts() {
+        let key = RoomKey::generate();
+        let sealed = key.encrypt(b"hello");
+        assert_eq!(key.decrypt(&sealed), Some(b"hello".to_vec()));
+        assert_eq!(RoomKey::generate().decrypt(&sealed), None);
+        assert_eq!(key.decrypt(&sealed[..NONCE_LENGTH - 1]), None);
+    }
+
+    #[test]
+    fn password_keys_depend_on_the_room() {
+        let key = RoomKey::from_password("test", "chat", "secret");
+        assert_eq!(key, RoomKey::from_password("test", "chat", "secret"));
+        assert_ne!(key, RoomKey::from_password("test", "other", "secret"));
+    }
+}
@@ -0,0 +1,81 @@
+use libp2p::{Multiaddr, PeerId};
+
+/// Snapshot of the swarm's state, shown by `/netinfo` and the diagnostics tab
+#[derive(Default, Clone)]
+pub struct NetworkInfo {
+    pub peer_id: Option<PeerId>,
+    pub listen_addrs: Vec<Multiaddr>,
+    pub external_addrs: Vec<Multiaddr>,
+    // Every connected peer with the addresses of its open connections
+    pub connections: Vec<(PeerId, Vec<Multiaddr>)>,
+    // Non-empty Kademlia buckets as (bucket index, number of peers)
+    pub buckets: Vec<(u32, usize)>,
+    // Peers each subscribed room's messages are sent to. Every peer is added as an explicit
+    // peer, so this is every connected subscriber rather than the gossipsub mesh.
+    pub room_peers: Vec<(String, Vec<PeerId>)>,
+    // Outstanding nickname lookups, as (query id, room the message is for)
+    pub pending_messages: Vec<(String, String)>,
+    // Outstanding nickname lookups for private requests, as (query id, request type)
+    pub pending_private_messages: Vec<(String, String)>,
+}
+
+impl NetworkInfo {
+    pub fn routing_table_size(&self) -> usize {
+        self.buckets.iter().map(|(_, entries)| entries).sum()
+    }
+
+    /// Short summary of each section, one line per entry
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![];
+
+        let peer_id = self
+            .peer_id
+            .map(|peer_id| peer_id.to_base58())
+            .unwrap_or("unknown".to_string());
+        lines.push(format!("Peer ID: {peer_id}"));
+
+        lines.push(format!("Listen addresses: {}", self.listen_addrs.len()));
+        for addr in &self.listen_addrs {
+            lines.push(format!("  {addr}"));
+        }
+
+        lines.push(format!("External addresses: {}", self.external_addrs.len()));
+        for addr in &self.external_addrs {
+            lines.push(format!("  {addr}"));
+        }
+
+        lines.push(format!("Connected peers: {}", self.connections.len()));
+        for (peer_id, addrs) in &self.connections {
+            let addrs: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+            lines.push(format!("  {peer_id} via {}", addrs.join(", ")));
+        }
+
+        lines.push(format!(
+            "Routing table: {} peers in {} buckets",
+            self.routing_table_size(),
+            self.buckets.len()
+        ));
+        for (index, entries) in &self.buckets {
+            lines.push(format!("  bucket {index}: {entries}"));
+        }
+
+        lines.push("Room peers:".to_string());
+        for (room, peers) in &self.room_peers {
+            lines.push(format!("  {room}: {}", peers.len()));
+        }
+
+        lines.push(format!(
+            "Pending queries: {} messages, {} private requests",
+            self.pending_messages.len(),
+            self.pending_private_messages.len()
+        ));
+        for (query_id, room) in &self.pending_messages {
+            lines.push(format!("  {query_id}: message in {room}"));
+        }
+        for (query_id, request_type) in &self.pending_private_messages {
+            lines.push(format!("  {query_id}: {request_type}"));
+        }
+
+        lines
+    }
+}
@@ -0,0 +1,100 @@
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+/// Most messages kept for each room, and sent in reply to a history request
+pub(crate) const HISTORY_LIMIT: usize = 100;
+// Messages asked for automatically when joining a room
+pub(crate) const HISTORY_SYNC_LIMIT: usize = 50;
+
+/// A chat message as it was published, so private rooms stay encrypted when it is passed on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct HistoryEntry {
+    // The id in the signed message, used to drop messages we already have
+    pub(crate) id: String,
+    // Taken from the signed message rather than trusted from the peer that sent the history
+    pub(crate) author: PeerId,
+    pub(crate) data: Vec<u8>,
+    // In seconds since the unix epoch, also taken from the signed message
+    pub(crate) sent_at: u64,
+}
+
+/// The last messages in a room, oldest first, kept for peers that join later
+#[derive(Debug, Default)]
+pub(crate) struct RoomHistory {
+    entries: Vec<HistoryEntry>,
+}
+
+impl RoomHistory {
+    /// Add a message in order, returning false if it was already known
+    pub(crate) fn insert(&mut self, entry: HistoryEntry) -> bool {
+        if self.entries.iter().any(|known| known.id == entry.id) {
+            return false;
+        }
+        let position = self
+            .entries
+            .partition_point(|known| known.sent_at <= entry.sent_at);
+        self.entries.insert(position, entry);
+        if self.entries.len() > HISTORY_LIMIT {
+            self.entries.remove(0);
+        }
+        true
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
+    /// The last `limit` messages, optionally only those sent since a unix time
+    pub(crate) fn recent(&self, limit: usize, since: Option<u64>) -> Vec<HistoryEntry> {
+        let entries: Vec<&HistoryEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| since.is_none_or(|since| entry.sent_at >= since))
+            .collect();
+        let skip = entries.len().saturating_sub(limit.min(HISTORY_LIMIT));
+        entries.into_iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: usize, sent_at: u64) -> HistoryEntry {
+        HistoryEntry {
+            id: id.to_string(),
+            author: PeerId::random(),
+            data: vec![],
+            sent_at,
+        }
+    }
+
+    fn ids(entries: &[HistoryEntry]) -> Vec<String> {
+        entries.iter().map(|entry| entry.id.clone()).collect()
+    }
+
+    #[test]
+    fn insert_keeps_messages_in_order_once() {
+        let mut history = RoomHistory::default();
+        assert!(history.insert(entry(1, 30)));
+        assert!(history.insert(entry(2, 10)));
+        assert!(history.insert(entry(3, 20)));
+        assert!(!history.insert(entry(2, 40)));
+
+        assert_eq!(ids(&history.recent(10, None)), ["2", "3", "1"]);
+    }
+
+    #[test]
+    fn recent_limits_by_count_and_time() {
+        let mut history = RoomHistory::default();
+        for id in 0..HISTORY_LIMIT + 10 {
+            history.insert(entry(id, id as u64));
+        }
+
+        // The oldest messages are dropped once the history is full
+        assert!(history.get("0").is_none());
+        assert_eq!(history.recent(usize::MAX, None).len(), HISTORY_LIMIT);
+        assert_eq!(ids(&history.recent(2, None)), ["108", "109"]);
+        assert_eq!(ids(&history.recent(10, Some(107))), ["107", "108", "109"]);
+    }
+}
@@ -0,0 +1,38 @@
+use super::signed::Signed;
+
+use serde::{Deserialize, Serialize};
+
+/// Everything that can be published on a room's topic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum RoomMessage {
+    // A signed `ChatMessage`
+    Chat(Signed),
+    // A signed `Moderation`
+    Moderation(Signed),
+    // The room's whole moderation log, sent when a peer joins
+    ModerationLog(Vec<Signed>),
+}
+
+/// A chat message signed by its author, so it can be checked when it is passed on in a
+/// room's history by someone else
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ChatMessage {
+    // Chosen by the author and starting with their peer id, so nobody else can send a message
+    // under it. Used to drop messages we already have, and to delete or pin them.
+    pub(crate) id: String,
+    pub(crate) workspace: String,
+    pub(crate) room: String,
+    pub(crate) text: String,
+    // Seconds since the unix epoch, as given by the author
+    pub(crate) sent_at: u64,
+}
+
+impl RoomMessage {
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, serde_cbor::Error> {
+        serde_cbor::to_vec(self)
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, serde_cbor::Error> {
+        serde_cbor::from_slice(bytes)
+    }
+}
@@ -0,0 +1,612 @@
+use super::signed::Signed;
+use crate::state::unix_time;
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+// Deleted and unpinned messages kept in the log, so it stays small enough to send
+const MAX_REMOVALS: usize = 50;
+// How far ahead of our clock an action can be dated, as peers' clocks differ a little
+const MAX_CLOCK_SKEW: u64 = 60;
+
+/// Something a room's owner or moderators can do
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    Kick(PeerId),
+    // Muted until the given unix time
+    Mute(PeerId, u64),
+    Ban(PeerId),
+    Unban(PeerId),
+    // Gossipsub id of the message, which is the same on every peer
+    Delete(String),
+    AddModerator(PeerId),
+    RemoveModerator(PeerId),
+    // Peers allowed to post in an announcement room
+    AddPublisher(PeerId),
+    RemovePublisher(PeerId),
+    // An empty topic clears it
+    SetTopic(String),
+    // Gossipsub id of the message, whose text is looked up in the room's history
+    Pin(String),
+    Unpin(String),
+}
+
+/// What an action changes, so only the latest action about each thing is kept in the log
+#[derive(PartialEq, Eq, Hash)]
+enum Slot {
+    Topic,
+    Pin(String),
+    Publisher(PeerId),
+    Ban(PeerId),
+    Mute(PeerId),
+    Moderator(PeerId),
+}
+
+impl Action {
+    /// The peer an action is aimed at, if any
+    pub fn target(&self) -> Option<PeerId> {
+        match self {
+            Action::Kick(peer)
+            | Action::Mute(peer, _)
+            | Action::Ban(peer)
+            | Action::Unban(peer)
+            | Action::AddModerator(peer)
+            | Action::RemoveModerator(peer)
+            | Action::AddPublisher(peer)
+            | Action::RemovePublisher(peer) => Some(*peer),
+            Action::Delete(_) | Action::SetTopic(_) | Action::Pin(_) | Action::Unpin(_) => None,
+        }
+    }
+
+    /// Shown in the room when the action is taken
+    pub fn notice(&self, target: &str, by: &str) -> String {
+        match self {
+            Action::Kick(_) => format!("{target} was kicked by {by}"),
+            Action::Mute(_, until) => format!(
+                "{target} was muted for {} minute(s) by {by}",
+                minutes_until(*until)
+            ),
+            Action::Ban(_) => format!("{target} was banned by {by}"),
+            Action::Unban(_) => format!("{target} was unbanned by {by}"),
+            Action::Delete(_) => format!("A message was deleted by {by}"),
+            Action::AddModerator(_) => format!("{target} was made a moderator by {by}"),
+            Action::RemoveModerator(_) => format!("{target} is no longer a moderator"),
+            Action::AddPublisher(_) => format!("{target} was made a publisher by {by}"),
+            Action::RemovePublisher(_) => format!("{target} is no longer a publisher"),
+            Action::SetTopic(topic) if topic.is_empty() => format!("{by} cleared the topic"),
+            Action::SetTopic(topic) => format!("{by} set the topic to: {topic}"),
+            Action::Pin(_) => format!("{by} pinned a message"),
+            Action::Unpin(_) => format!("{by} unpinned a message"),
+        }
+    }
+
+    // Everything but kicks and deletions only matters until it is replaced
+    fn slot(&self) -> Option<Slot> {
+        match self {
+            Action::Ban(peer) | Action::Unban(peer) => Some(Slot::Ban(*peer)),
+            Action::Mute(peer, _) => Some(Slot::Mute(*peer)),
+            Action::AddModerator(peer) | Action::RemoveModerator(peer) => {
+                Some(Slot::Moderator(*peer))
+            }
+            Action::SetTopic(_) => Some(Slot::Topic),
+            Action::Pin(message_id) | Action::Unpin(message_id) => {
+                Some(Slot::Pin(message_id.clone()))
+            }
+            Action::AddPublisher(peer) | Action::RemovePublisher(peer) => {
+                Some(Slot::Publisher(*peer))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A message pinned in a room, with its author and text if it is in our history of the room
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinnedMessage {
+    pub id: String,
+    pub author: Option<PeerId>,
+    pub text: Option<String>,
+}
+
+/// A room's topic line, pinned messages (oldest first) and who can post if it is for announcements
+#[derive(Debug, Clone, Default)]
+pub struct RoomDetails {
+    pub topic: String,
+    pub pins: Vec<PinnedMessage>,
+    pub publishers: Vec<PeerId>,
+}
+
+/// Whole minutes left until a unix time, rounded up
+pub(crate) fn minutes_until(until: u64) -> u64 {
+    until.saturating_sub(unix_time()).div_ceil(60)
+}
+
+/// An action tied to the room it was taken in, so it can't be replayed in another room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Moderation {
+    pub(crate) workspace: String,
+    pub(crate) room: String,
+    pub(crate) action: Action,
+    // Seconds since the unix epoch, which keeps repeated actions distinct. Chosen by the
+    // signer, so it is never used to decide whether they were allowed to act, and actions
+    // dated in the future are refused.
+    pub(crate) issued_at: u64,
+}
+
+/// Who runs a room, who has been banned or muted in it, and its topic and pins
+#[derive(Debug, Default)]
+pub(crate) struct ModerationState {
+    owner: Option<PeerId>,
+    moderators: HashSet<PeerId>,
+    banned: HashSet<PeerId>,
+    muted: HashMap<PeerId, u64>,
+    deleted: HashSet<String>,
+    topic: String,
+    // Ids of the pinned messages, oldest first
+    pins: Vec<String>,
+    publishers: Vec<PeerId>,
+}
+
+impl ModerationState {
+    fn new(owner: Option<PeerId>) -> Self {
+        Self {
+            owner,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn can_moderate(&self, peer: &PeerId) -> bool {
+        self.owner == Some(*peer) || self.moderators.contains(peer)
+    }
+
+    /// Whether `signer` is allowed to take an action. Only the owner can appoint moderators
+    /// and publishers, and moderators can't act against the owner or each other.
+    fn allows(&self, signer: &PeerId, action: &Action) -> bool {
+        if !self.can_moderate(signer) {
+            return false;
+        }
+        let is_owner = self.owner == Some(*signer);
+        match action {
+            Action::AddModerator(_)
+            | Action::RemoveModerator(_)
+            | Action::AddPublisher(_)
+            | Action::RemovePublisher(_) => is_owner,
+            _ => match action.target() {
+                Some(target) => {
+                    self.owner != Some(target) && (is_owner || !self.moderators.contains(&target))
+                }
+                None => true,
+            },
+        }
+    }
+
+    fn apply(&mut self, action: &Action) {
+        match action {
+            // Kicked peers leave the room themselves, so there is nothing to remember
+            Action::Kick(_) => {}
+            Action::Mute(peer, until) => {
+                self.muted.insert(*peer, *until);
+            }
+            Action::Ban(peer) => {
+                self.banned.insert(*peer);
+            }
+            Action::Unban(peer) => {
+                self.banned.remove(peer);
+                self.muted.remove(peer);
+            }
+            Action::Delete(message_id) => {
+                self.deleted.insert(message_id.clone());
+                self.pins.retain(|pinned| pinned != message_id);
+            }
+            Action::AddModerator(peer) => {
+                self.moderators.insert(*peer);
+            }
+            Action::RemoveModerator(peer) => {
+                self.moderators.remove(peer);
+            }
+            Action::AddPublisher(peer) => {
+                if !self.publishers.contains(peer) {
+                    self.publishers.push(*peer);
+                }
+            }
+            Action::RemovePublisher(peer) => {
+                self.publishers.retain(|publisher| publisher != peer);
+            }
+            Action::SetTopic(topic) => {
+                self.topic = topic.clone();
+            }
+            Action::Pin(message_id) => {
+                if !self.pins.contains(message_id) && !self.deleted.contains(message_id) {
+                    self.pins.push(message_id.clone());
+                }
+            }
+            Action::Unpin(message_id) => {
+                self.pins.retain(|pinned| pinned != message_id);
+            }
+        }
+    }
+
+    /// Whether a peer may post in the room if it is for announcements
+    pub(crate) fn can_publish(&self, peer: &PeerId) -> bool {
+        self.owner == Some(*peer) || self.publishers.contains(peer)
+    }
+
+    pub(crate) fn is_banned(&self, peer: &PeerId) -> bool {
+        self.banned.contains(peer)
+    }
+
+    /// When a peer's mute ends, if they are still muted at `now`
+    pub(crate) fn muted_until(&self, peer: &PeerId, now: u64) -> Option<u64> {
+        self.muted.get(peer).copied().filter(|until| *until > now)
+    }
+
+    pub(crate) fn is_deleted(&self, message_id: &str) -> bool {
+        self.deleted.contains(message_id)
+    }
+
+    pub(crate) fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub(crate) fn pins(&self) -> &[String] {
+        &self.pins
+    }
+
+    pub(crate) fn publishers(&self) -> &[PeerId] {
+        &self.publishers
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    signer: PeerId,
+    moderation: Moderation,
+    signed: Signed,
+}
+
+/// Every signed action taken in a room, in the order they were accepted.
+///
+/// A signer's authority is checked against the state when their action arrives, so a
+/// moderator that has been removed can't backdate an action to before their removal.
+/// The log is sent in this order to peers that join the room later, who check every
+/// signature and permission again as they add each action.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ModerationLog {
+    entries: Vec<Entry>,
+}
+
+impl ModerationLog {
+    /// Rebuild a saved log, keeping its order and skipping anything with a bad signature
+    pub(crate) fn from_signed(signed: Vec<Signed>) -> Self {
+        let entries: Vec<Entry> = signed
+            .into_iter()
+            .filter_map(|signed| {
+                let (signer, moderation) = signed.verify()?;
+                Some(Entry {
+                    signer,
+                    moderation,
+                    signed,
+                })
+            })
+            .collect();
+        let mut log = Self { entries };
+        log.compact(unix_time());
+        log
+    }
+
+    pub(crate) fn signed(&self) -> Vec<Signed> {
+        self.entries
+            .iter()
+            .map(|entry| entry.signed.clone())
+            .collect()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Replay the log to find the room's current state, dropping anything its signer was no
+    /// longer allowed to do by the time it was accepted
+    pub(crate) fn state(&self, owner: Option<PeerId>) -> ModerationState {
+        let mut state = ModerationState::new(owner);
+        for entry in &self.entries {
+            if state.allows(&entry.signer, &entry.moderation.action) {
+                state.apply(&entry.moderation.action);
+            }
+        }
+        state
+    }
+
+    /// Add an action if it is new, correctly signed and allowed, returning who took it
+    pub(crate) fn insert(
+        &mut self,
+        signed: Signed,
+        workspace: &str,
+        room: &str,
+        owner: Option<PeerId>,
+    ) -> Option<(PeerId, Action)> {
+        if self.entries.iter().any(|entry| entry.signed == signed) {
+            return None;
+        }
+
+        let (signer, moderation): (PeerId, Moderation) = signed.verify()?;
+        if moderation.workspace != workspace || moderation.room != room {
+            return None;
+        }
+        if !self.state(owner).allows(&signer, &moderation.action) {
+            return None;
+        }
+
+        // Otherwise an action dated far ahead would stop anything replacing it
+        let now = unix_time();
+        if moderation.issued_at > now + MAX_CLOCK_SKEW {
+            return None;
+        }
+
+        // Replaced actions are dropped from the log, so an old one sent again mustn't undo them
+        if let Some(slot) = moderation.action.slot() {
+            let replaced = self.entries.iter().any(|entry| {
+                entry.moderation.action.slot().as_ref() == Some(&slot)
+                    && entry.moderation.issued_at > moderation.issued_at
+            });
+            if replaced {
+                return None;
+            }
+        }
+        if let Action::Mute(_, until) = moderation.action {
+            if until <= now {
+                return None;
+            }
+        }
+
+        let action = moderation.action.clone();
+        // Kicks only matter when they happen, so they aren't sent to peers that join later
+        if !matches!(action, Action::Kick(_)) {
+            self.entries.push(Entry {
+                signer,
+                moderation,
+                signed,
+            });
+            self.compact(now);
+        }
+        Some((signer, action))
+    }
+
+    // Keep the log small enough to send in one message. Only the latest change to the topic,
+    // each pin, and each peer's ban, mute, publisher and moderator status is kept, along with
+    // the most recent deletions and unpins. Mutes that have ended are dropped too. An older
+    // change to a moderator is still kept if it was the last one before an action taken by or
+    // against them, as whether that action was allowed depends on it.
+    fn compact(&mut self, now: u64) {
+        let mut latest = HashSet::new();
+        let mut involved = HashSet::new();
+        let mut removals = 0;
+        let mut entries = vec![];
+        for entry in self.entries.drain(..).rev() {
+            let action = &entry.moderation.action;
+            let moderator = matches!(action, Action::AddModerator(_) | Action::RemoveModerator(_));
+            if let Some(slot) = action.slot() {
+                let latest_for_slot = latest.insert(slot);
+                let needed =
+                    moderator && action.target().is_some_and(|peer| involved.remove(&peer));
+                if !latest_for_slot && !needed {
+                    continue;
+                }
+            }
+            match action {
+                Action::Mute(_, until) if *until <= now => continue,
+                Action::Delete(_) | Action::Unpin(_) => {
+                    removals += 1;
+                    if removals > MAX_REMOVALS {
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            involved.insert(entry.signer);
+            if let Some(target) = action.target().filter(|_| !moderator) {
+                involved.insert(target);
+            }
+            entries.push(entry);
+        }
+        entries.reverse();
+        self.entries = entries;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    const WORKSPACE: &str = "test";
+    const ROOM: &str = "chat";
+
+    struct Room {
+        owner: Keypair,
+        log: ModerationLog,
+    }
+
+    impl Room {
+        fn new() -> Self {
+            Self {
+                owner: Keypair::generate_ed25519(),
+                log: ModerationLog::default(),
+            }
+        }
+
+        fn owner(&self) -> Option<PeerId> {
+            Some(self.owner.public().to_peer_id())
+        }
+
+        fn act(&mut self, signer: &Keypair, action: Action, issued_at: u64) -> bool {
+            let moderation = Moderation {
+                workspace: WORKSPACE.to_string(),
+                room: ROOM.to_string(),
+                action,
+                issued_at,
+            };
+            let signed = Signed::sign(signer, &moderation).unwrap();
+            let owner = self.owner();
+            self.log.insert(signed, WORKSPACE, ROOM, owner).is_some()
+        }
+
+        fn state(&self) -> ModerationState {
+            self.log.state(self.owner())
+        }
+    }
+
+    fn peer(keypair: &Keypair) -> PeerId {
+        keypair.public().to_peer_id()
+    }
+
+    #[test]
+    fn only_the_owner_appoints_moderators() {
+        let mut room = Room::new();
+        let owner = room.owner.clone();
+        let moderator = Keypair::generate_ed25519();
+        let member = Keypair::generate_ed25519();
+
+        assert!(!room.act(&member, Action::AddModerator(peer(&member)), 1));
+        assert!(room.act(&owner, Action::AddModerator(peer(&moderator)), 1));
+        assert!(!room.act(&moderator, Action::AddModerator(peer(&member)), 2));
+        assert!(!room.act(&moderator, Action::AddPublisher(peer(&member)), 2));
+        assert!(room.state().can_moderate(&peer(&moderator)));
+        assert!(!room.state().can_moderate(&peer(&member)));
+    }
+
+    #[test]
+    fn moderators_cannot_act_against_the_owner_or_each_other() {
+        let mut room = Room::new();
+        let owner = room.owner.clone();
+        let first = Keypair::generate_ed25519();
+        let second = Keypair::generate_ed25519();
+        let member = Keypair::generate_ed25519();
+        room.act(&owner, Action::AddModerator(peer(&first)), 1);
+        room.act(&owner, Action::AddModerator(peer(&second)), 2);
+
+        assert!(!room.act(&first, Action::Ban(peer(&owner)), 3));
+        assert!(!room.act(&first, Action::Ban(peer(&second)), 3));
+        assert!(room.act(&first, Action::Ban(peer(&member)), 3));
+        assert!(room.act(&owner, Action::Ban(peer(&second)), 4));
+
+        let state = room.state();
+        assert!(state.is_banned(&peer(&member)));
+        assert!(state.is_banned(&peer(&second)));
+        assert!(!state.is_banned(&peer(&owner)));
+    }
+
+    #[test]
+    fn removed_moderators_cannot_backdate_actions() {
+        let mut room = Room::new();
+        let owner = room.owner.clone();
+        let moderator = Keypair::generate_ed25519();
+        let member = Keypair::generate_ed25519();
+        room.act(&owner, Action::AddModerator(peer(&moderator)), 10);
+        room.act(&owner, Action::RemoveModerator(peer(&moderator)), 20);
+
+        assert!(!room.act(&moderator, Action::Ban(peer(&member)), 15));
+        assert!(!room.state().is_banned(&peer(&member)));
+    }
+
+    #[test]
+    fn actions_are_tied_to_their_room() {
+        let mut room = Room::new();
+        let member = Keypair::generate_ed25519();
+        let moderation = Moderation {
+            workspace: WORKSPACE.to_string(),
+            room: "elsewhere".to_string(),
+            action: Action::Ban(peer(&member)),
+            issued_at: 1,
+        };
+        let signed = Signed::sign(&room.owner, &moderation).unwrap();
+        let owner = room.owner();
+        assert!(room.log.insert(signed, WORKSPACE, ROOM, owner).is_none());
+        assert!(room.log.is_empty());
+    }
+
+    #[test]
+    fn replaying_a_saved_log_gives_the_same_state() {
+        let mut room = Room::new();
+        let owner = room.owner.clone();
+        let moderator = Keypair::generate_ed25519();
+        let member = Keypair::generate_ed25519();
+        room.act(&owner, Action::AddModerator(peer(&moderator)), 1);
+        room.act(&moderator, Action::Ban(peer(&member)), 2);
+        room.act(&moderator, Action::SetTopic("old".to_string()), 3);
+        room.act(&owner, Action::SetTopic("new".to_string()), 4);
+        room.act(&owner, Action::RemoveModerator(peer(&moderator)), 5);
+
+        // Only the latest topic is kept
+        assert_eq!(room.log.signed().len(), 4);
+        let replayed = ModerationLog::from_signed(room.log.signed()).state(room.owner());
+        assert!(replayed.is_banned(&peer(&member)));
+        assert!(!replayed.can_moderate(&peer(&moderator)));
+        assert_eq!(replayed.topic(), "new");
+    }
+
+    #[test]
+    fn future_actions_cannot_block_later_changes() {
+        let mut room = Room::new();
+        let owner = room.owner.clone();
+        let moderator = Keypair::generate_ed25519();
+        room.act(&owner, Action::AddModerator(peer(&moderator)), 1);
+
+        let far_ahead = unix_time() + 365 * 24 * 60 * 60;
+        assert!(!room.act(&moderator, Action::SetTopic("stuck".to_string()), far_ahead));
+        assert!(room.act(&owner, Action::SetTopic("new".to_string()), unix_time()));
+        assert_eq!(room.state().topic(), "new");
+    }
+
+    #[test]
+    fn repeated_bans_and_appointments_keep_the_log_small() {
+        let mut room = Room::new();
+        let owner = room.owner.clone();
+        let member = Keypair::generate_ed25519();
+        for issued_at in 0..100 {
+            room.act(&owner, Action::Ban(peer(&member)), issued_at * 2);
+            room.act(&owner, Action::Unban(peer(&member)), issued_at * 2 + 1);
+            room.act(&owner, Action::AddModerator(peer(&member)), issued_at * 2);
+            room.act(
+                &owner,
+                Action::RemoveModerator(peer(&member)),
+                issued_at * 2 + 1,
+            );
+        }
+
+        // The last unban, the last removal and the removal before that unban
+        assert_eq!(room.log.signed().len(), 3);
+        let state = room.state();
+        assert!(!state.is_banned(&peer(&member)));
+        assert!(!state.can_moderate(&peer(&member)));
+    }
+
+    #[test]
+    fn compaction_keeps_what_moderators_actions_depend_on() {
+        let mut room = Room::new();
+        let owner = room.owner.clone();
+        let moderator = Keypair::generate_ed25519();
+        let member = Keypair::generate_ed25519();
+        room.act(&owner, Action::AddModerator(peer(&moderator)), 1);
+        room.act(&moderator, Action::Ban(peer(&member)), 2);
+        room.act(&owner, Action::RemoveModerator(peer(&moderator)), 3);
+        room.act(&owner, Action::AddModerator(peer(&moderator)), 4);
+        room.act(&owner, Action::RemoveModerator(peer(&moderator)), 5);
+
+        let replayed = ModerationLog::from_signed(room.log.signed()).state(room.owner());
+        assert!(replayed.is_banned(&peer(&member)));
+        assert!(!replayed.can_moderate(&peer(&moderator)));
+    }
+
+    #[test]
+    fn replaced_actions_cannot_be_sent_again() {
+        let mut room = Room::new();
+        let owner = room.owner.clone();
+        room.act(&owner, Action::SetTopic("old".to_string()), 1);
+        room.act(&owner, Action::SetTopic("new".to_string()), 2);
+
+        assert!(!room.act(&owner, Action::SetTopic("old".to_string()), 1));
+        assert_eq!(room.state().topic(), "new");
+    }
+}
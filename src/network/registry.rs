@@ -0,0 +1,518 @@
+use super::signed::Signed;
+
+use libp2p::{identity::Keypair, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+/// Room every workspace starts with
+pub(crate) const GLOBAL_ROOM: &str = "Global";
+
+/// Most rooms a workspace can have, so the whole registry fits in one gossipsub message
+pub(crate) const MAX_ROOMS: usize = 64;
+
+/// Most rooms one peer can create in a workspace, so nobody can take all of them
+pub(crate) const MAX_ROOMS_PER_CREATOR: usize = 8;
+
+/// Most bytes a room's description can have
+pub(crate) const MAX_DESCRIPTION_LENGTH: usize = 256;
+
+/// Most bytes a room name, alias or nickname can have
+pub(crate) const MAX_NAME_LENGTH: usize = 64;
+
+/// Most aliases and former names a room can have between them
+pub(crate) const MAX_OTHER_NAMES: usize = 8;
+
+/// Details about a room, shared with every peer in the workspace
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub description: String,
+    // Global has no creator
+    pub creator: Option<PeerId>,
+    pub creator_nickname: String,
+    // Seconds since the unix epoch
+    pub created_at: u64,
+    // Only the creator and the publishers they choose can post. Missing from older registries.
+    #[serde(default)]
+    pub announcement: bool,
+    // A signed `RoomNames`, set once the creator renames the room or gives it aliases
+    #[serde(default)]
+    pub(crate) names: Option<Signed>,
+    // A signed `RoomCreation`, which is what makes `creator` the room's owner
+    #[serde(default)]
+    pub(crate) signature: Option<Signed>,
+}
+
+/// Everything about a room that its creator signs when creating it
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct RoomCreation {
+    workspace: String,
+    room: String,
+    description: String,
+    creator_nickname: String,
+    created_at: u64,
+    announcement: bool,
+}
+
+impl RoomInfo {
+    /// Sign the room as its creator, making us its owner
+    pub(crate) fn sign(&mut self, keypair: &Keypair, workspace: &str, room: &str) -> bool {
+        self.creator = Some(keypair.public().to_peer_id());
+        self.signature = Signed::sign(keypair, &self.creation(workspace, room));
+        self.signature.is_some()
+    }
+
+    /// The room's creator, if they signed these details for this room
+    pub(crate) fn verified_creator(&self, workspace: &str, room: &str) -> Option<PeerId> {
+        let (signer, creation): (PeerId, RoomCreation) = self.signature.as_ref()?.verify()?;
+        (Some(signer) == self.creator && creation == self.creation(workspace, room))
+            .then_some(signer)
+    }
+
+    /// Whether the room's details are small enough for peers to accept
+    pub(crate) fn within_limits(&self, room: &str) -> bool {
+        room.len() <= MAX_NAME_LENGTH
+            && self.description.len() <= MAX_DESCRIPTION_LENGTH
+            && self.creator_nickname.len() <= MAX_NAME_LENGTH
+    }
+
+    fn creation(&self, workspace: &str, room: &str) -> RoomCreation {
+        RoomCreation {
+            workspace: workspace.to_string(),
+            room: room.to_string(),
+            description: self.description.clone(),
+            creator_nickname: self.creator_nickname.clone(),
+            created_at: self.created_at,
+            announcement: self.announcement,
+        }
+    }
+
+    /// The room's names, if they were signed by its creator for this room
+    pub(crate) fn verified_names(&self, workspace: &str, room: &str) -> Option<RoomNames> {
+        let (signer, names): (PeerId, RoomNames) = self.names.as_ref()?.verify()?;
+        (Some(signer) == self.creator
+            && names.workspace == workspace
+            && names.room == room
+            && names.within_limits())
+        .then_some(names)
+    }
+
+    // Of two versions of a room from the same creator, the one every peer ends up with.
+    // The latest names win, and ties between any two versions fall back to the signed bytes.
+    fn merged(&self, other: &RoomInfo, workspace: &str, room: &str) -> RoomInfo {
+        let mut merged = match other.signature > self.signature {
+            true => other.clone(),
+            false => self.clone(),
+        };
+        let names_order = |info: &RoomInfo| {
+            info.verified_names(workspace, room)
+                .map(|names| (names.issued_at, info.names.clone()))
+        };
+        if names_order(other) > names_order(self) {
+            merged.names = other.names.clone();
+        } else {
+            merged.names = self.names.clone();
+        }
+        merged
+    }
+
+    // Drop names that weren't signed by the creator or are too long, so they are never passed on
+    fn without_forged_names(&self, workspace: &str, room: &str) -> RoomInfo {
+        let mut info = self.clone();
+        if info.verified_names(workspace, room).is_none() {
+            info.names = None;
+        }
+        info
+    }
+}
+
+/// The name a room is shown with, and other names that lead to it.
+///
+/// A room's id is the name it was created with, which is also its gossipsub topic,
+/// so renaming a room never moves its messages or moderation log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoomNames {
+    pub workspace: String,
+    // The room's id
+    pub room: String,
+    pub name: String,
+    // Short names chosen by the owner
+    pub aliases: Vec<String>,
+    // Names the room used to have, kept so they still lead to it
+    pub former_names: Vec<String>,
+    // Seconds since the unix epoch, the latest change wins
+    pub issued_at: u64,
+}
+
+impl RoomNames {
+    /// A room that has never been renamed
+    pub fn new(workspace: &str, room: &str) -> Self {
+        Self {
+            workspace: workspace.to_string(),
+            room: room.to_string(),
+            name: room.to_string(),
+            aliases: vec![],
+            former_names: vec![],
+            issued_at: 0,
+        }
+    }
+
+    /// Whether `name` leads to this room
+    pub fn matches(&self, name: &str) -> bool {
+        self.room == name
+            || self.name == name
+            || self.aliases.iter().any(|alias| alias == name)
+            || self.former_names.iter().any(|former| former == name)
+    }
+
+    /// Whether there are few enough names, each short enough, for peers to accept
+    pub fn within_limits(&self) -> bool {
+        self.aliases.len() + self.former_names.len() <= MAX_OTHER_NAMES
+            && std::iter::once(&self.name)
+                .chain(&self.aliases)
+                .chain(&self.former_names)
+                .all(|name| name.len() <= MAX_NAME_LENGTH)
+    }
+}
+
+/// Grow-only map of every room in a workspace.
+///
+/// Rooms are never removed, and when two peers disagree about a room the same
+/// version is picked on both sides, so merging registries in any order gives
+/// every peer the same list. The whole map is sent each time as it stays small.
+pub(crate) struct RoomRegistry {
+    rooms: BTreeMap<String, RoomInfo>,
+}
+
+impl Default for RoomRegistry {
+    fn default() -> Self {
+        let global = RoomInfo {
+            description: "Everyone in the workspace".to_string(),
+            ..Default::default()
+        };
+        Self {
+            rooms: BTreeMap::from([(GLOBAL_ROOM.to_string(), global)]),
+        }
+    }
+}
+
+impl RoomRegistry {
+    /// Add a room, returning false if it was already known, the registry is full,
+    /// or its creator already has `MAX_ROOMS_PER_CREATOR` rooms
+    pub(crate) fn insert(&mut self, room: String, info: RoomInfo) -> bool {
+        let full = self.is_full() || self.is_full_for(info.creator);
+        match self.rooms.entry(room) {
+            Entry::Vacant(entry) if !full => {
+                entry.insert(info);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.rooms.len() >= MAX_ROOMS
+    }
+
+    /// Whether a peer has created as many rooms as they can
+    pub(crate) fn is_full_for(&self, creator: Option<PeerId>) -> bool {
+        has_most_rooms(&self.rooms, creator)
+    }
+
+    /// Add every room from another registry, returning true if anything changed.
+    ///
+    /// Rooms not signed by their creator, or with details that are too long, are dropped.
+    /// Once a room is known its creator never changes, so nobody can take over a room by
+    /// claiming to have created it earlier.
+    /// Names are only taken from the room's creator, and the latest rename wins.
+    /// New rooms stop being added once there are `MAX_ROOMS`, or once their creator
+    /// has `MAX_ROOMS_PER_CREATOR`.
+    pub(crate) fn merge(&mut self, other: &BTreeMap<String, RoomInfo>, workspace: &str) -> bool {
+        let mut changed = false;
+        for (room, info) in other {
+            if info.verified_creator(workspace, room).is_none() || !info.within_limits(room) {
+                continue;
+            }
+            let info = info.without_forged_names(workspace, room);
+            let full = self.is_full() || self.is_full_for(info.creator);
+            match self.rooms.entry(room.clone()) {
+                Entry::Vacant(_) if full => {}
+                Entry::Vacant(entry) => {
+                    entry.insert(info);
+                    changed = true;
+                }
+                Entry::Occupied(mut entry) => {
+                    if info.creator != entry.get().creator {
+                        continue;
+                    }
+                    let merged = entry.get().merged(&info, workspace, room);
+                    if merged != *entry.get() {
+                        entry.insert(merged);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    /// Replace a room's names with a change signed by its creator
+    pub(crate) fn set_names(&mut self, room: &str, names: Signed) -> bool {
+        match self.rooms.get_mut(room) {
+            Some(info) => {
+                info.names = Some(names);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether another registry is missing rooms, or details, that we know about.
+    /// Uses the same order as `merge`, so two peers are never both ahead of each other,
+    /// and a room they know from a different creator is theirs to keep.
+    /// A full registry is never behind on rooms it has no space for.
+    pub(crate) fn is_ahead_of(&self, other: &BTreeMap<String, RoomInfo>, workspace: &str) -> bool {
+        self.rooms.iter().any(|(room, info)| match other.get(room) {
+            Some(theirs) => {
+                theirs.creator == info.creator && theirs.merged(info, workspace, room) != *theirs
+            }
+            None => other.len() < MAX_ROOMS && !has_most_rooms(other, info.creator),
+        })
+    }
+
+    /// Room names with Global first, as shown in the Select Room tab
+    pub(crate) fn rooms(&self) -> Vec<String> {
+        let mut rooms = vec![GLOBAL_ROOM.to_string()];
+        rooms.extend(
+            self.rooms
+                .keys()
+                .filter(|room| *room != GLOBAL_ROOM)
+                .cloned(),
+        );
+        rooms
+    }
+
+    pub(crate) fn get(&self, room: &str) -> Option<&RoomInfo> {
+        self.rooms.get(room)
+    }
+
+    pub(crate) fn infos(&self) -> impl Iterator<Item = (&String, &RoomInfo)> {
+        self.rooms.iter()
+    }
+
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, serde_cbor::Error> {
+        serde_cbor::to_vec(&self.rooms)
+    }
+
+    pub(crate) fn from_bytes(
+        bytes: &[u8],
+    ) -> Result<BTreeMap<String, RoomInfo>, serde_cbor::Error> {
+        serde_cbor::from_slice(bytes)
+    }
+}
+
+// Global has no creator, so it never counts against anyone
+fn has_most_rooms(rooms: &BTreeMap<String, RoomInfo>, creator: Option<PeerId>) -> bool {
+    creator.is_some()
+        && rooms
+            .values()
+            .filter(|info| info.creator == creator)
+            .count()
+            >= MAX_ROOMS_PER_CREATOR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKSPACE: &str = "test";
+
+    fn room(keypair: &Keypair, room: &str, description: &str) -> RoomInfo {
+        let mut info = RoomInfo {
+            description: description.to_string(),
+            creator_nickname: "creator".to_string(),
+            created_at: 1,
+            ..Default::default()
+        };
+        assert!(info.sign(keypair, WORKSPACE, room));
+        info
+    }
+
+    fn renamed(
+        keypair: &Keypair,
+        mut info: RoomInfo,
+        room: &str,
+        name: &str,
+        issued_at: u64,
+    ) -> RoomInfo {
+        let names = RoomNames {
+            name: name.to_string(),
+            issued_at,
+            ..RoomNames::new(WORKSPACE, room)
+        };
+        info.names = Signed::sign(keypair, &names);
+        info
+    }
+
+    fn registry(rooms: &[(&str, RoomInfo)]) -> RoomRegistry {
+        let mut registry = RoomRegistry::default();
+        for (room, info) in rooms {
+            registry.insert(room.to_string(), info.clone());
+        }
+        registry
+    }
+
+    fn rooms(registry: &RoomRegistry) -> BTreeMap<String, RoomInfo> {
+        registry
+            .infos()
+            .map(|(room, info)| (room.clone(), info.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn merge_converges_in_either_order() {
+        let creator = Keypair::generate_ed25519();
+        let first = room(&creator, "chat", "first");
+        let second = room(&creator, "chat", "second");
+        let other = room(&creator, "other", "");
+
+        let mut a = registry(&[("chat", first.clone())]);
+        let mut b = registry(&[("chat", second.clone()), ("other", other)]);
+        let (from_a, from_b) = (rooms(&a), rooms(&b));
+        assert!(a.merge(&from_b, WORKSPACE));
+        b.merge(&from_a, WORKSPACE);
+
+        assert_eq!(rooms(&a), rooms(&b));
+        assert!(!a.is_ahead_of(&rooms(&b), WORKSPACE));
+        assert!(!b.is_ahead_of(&rooms(&a), WORKSPACE));
+        // Ties between versions are broken by their signed bytes
+        let expected = if second.signature > first.signature {
+            second
+        } else {
+            first
+        };
+        assert_eq!(a.get("chat"), Some(&expected));
+    }
+
+    #[test]
+    fn latest_names_win_in_either_order() {
+        let creator = Keypair::generate_ed25519();
+        let info = room(&creator, "chat", "");
+        let older = renamed(&creator, info.clone(), "chat", "old", 1);
+        let newer = renamed(&creator, info, "chat", "new", 2);
+
+        let mut a = registry(&[("chat", older.clone())]);
+        let mut b = registry(&[("chat", newer.clone())]);
+        let (from_a, from_b) = (rooms(&a), rooms(&b));
+        a.merge(&from_b, WORKSPACE);
+        b.merge(&from_a, WORKSPACE);
+
+        assert_eq!(rooms(&a), rooms(&b));
+        let names = a.get("chat").unwrap().verified_names(WORKSPACE, "chat");
+        assert_eq!(names.map(|names| names.name), Some("new".to_string()));
+    }
+
+    #[test]
+    fn merge_drops_forged_names() {
+        let creator = Keypair::generate_ed25519();
+        let forger = Keypair::generate_ed25519();
+        let forged = renamed(&forger, room(&creator, "chat", ""), "chat", "mine", 1);
+
+        let mut registry = RoomRegistry::default();
+        let other = BTreeMap::from([("chat".to_string(), forged)]);
+        assert!(registry.merge(&other, WORKSPACE));
+        assert_eq!(registry.get("chat").unwrap().names, None);
+    }
+
+    #[test]
+    fn merge_drops_unsigned_rooms() {
+        let creator = Keypair::generate_ed25519();
+        let unsigned = RoomInfo {
+            creator: Some(creator.public().to_peer_id()),
+            ..Default::default()
+        };
+
+        let mut registry = RoomRegistry::default();
+        let other = BTreeMap::from([("unsigned".to_string(), unsigned)]);
+        assert!(!registry.merge(&other, WORKSPACE));
+        assert!(registry.get("unsigned").is_none());
+    }
+
+    #[test]
+    fn merge_drops_oversized_rooms_and_names() {
+        let creator = Keypair::generate_ed25519();
+        let long = "a".repeat(MAX_DESCRIPTION_LENGTH + 1);
+        let mut too_many = room(&creator, "chat", "");
+        let names = RoomNames {
+            aliases: (0..=MAX_OTHER_NAMES).map(|n| format!("alias{n}")).collect(),
+            issued_at: 1,
+            ..RoomNames::new(WORKSPACE, "chat")
+        };
+        too_many.names = Signed::sign(&creator, &names);
+
+        let mut registry = RoomRegistry::default();
+        let other = BTreeMap::from([
+            ("long".to_string(), room(&creator, "long", &long)),
+            ("chat".to_string(), too_many),
+        ]);
+        assert!(registry.merge(&other, WORKSPACE));
+        assert!(registry.get("long").is_none());
+        assert_eq!(registry.get("chat").unwrap().names, None);
+    }
+
+    #[test]
+    fn merge_never_changes_a_known_creator() {
+        let creator = Keypair::generate_ed25519();
+        let other_creator = Keypair::generate_ed25519();
+        let original = room(&creator, "chat", "original");
+        let mut registry = registry(&[("chat", original.clone())]);
+
+        let takeover = BTreeMap::from([("chat".to_string(), room(&other_creator, "chat", "mine"))]);
+        assert!(!registry.merge(&takeover, WORKSPACE));
+        assert_eq!(registry.get("chat"), Some(&original));
+    }
+
+    #[test]
+    fn merge_stops_adding_rooms_when_full() {
+        let other: BTreeMap<String, RoomInfo> = (0..MAX_ROOMS + 5)
+            .map(|n| {
+                let name = format!("room{n}");
+                let info = room(&Keypair::generate_ed25519(), &name, "");
+                (name, info)
+            })
+            .collect();
+
+        let mut registry = RoomRegistry::default();
+        registry.merge(&other, WORKSPACE);
+        assert!(registry.is_full());
+        assert_eq!(registry.rooms().len(), MAX_ROOMS);
+        let creator = Keypair::generate_ed25519();
+        assert!(!registry.insert("extra".to_string(), room(&creator, "extra", "")));
+    }
+
+    #[test]
+    fn one_creator_cannot_take_every_room() {
+        let creator = Keypair::generate_ed25519();
+        let other: BTreeMap<String, RoomInfo> = (0..MAX_ROOMS)
+            .map(|n| {
+                let name = format!("room{n}");
+                let info = room(&creator, &name, "");
+                (name, info)
+            })
+            .collect();
+
+        let mut registry = RoomRegistry::default();
+        registry.merge(&other, WORKSPACE);
+        assert_eq!(registry.rooms().len(), MAX_ROOMS_PER_CREATOR + 1);
+        assert!(registry.is_full_for(Some(creator.public().to_peer_id())));
+
+        // A peer that kept a different set of their rooms isn't behind
+        let mut elsewhere = RoomRegistry::default();
+        let later = other.clone().split_off("room5");
+        elsewhere.merge(&later, WORKSPACE);
+        assert!(!registry.is_ahead_of(&rooms(&elsewhere), WORKSPACE));
+
+        let someone_else = Keypair::generate_ed25519();
+        assert!(registry.insert("theirs".to_string(), room(&someone_else, "theirs", "")));
+    }
+}
@@ -0,0 +1,106 @@
+use super::crypto::RoomKey;
+use super::history::HistoryEntry;
+use super::registry::RoomInfo;
+use super::signed::Signed;
+
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io;
+
+const INVITE_LINK_PREFIX: &str = "swapbytes:";
+
+/// Requests sent between peers about rooms, separate from private messaging
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum RoomRequest {
+    // A signed `Invitation`
+    Invite(Signed),
+    // The last messages in a room, identified by its topic so private room names stay hidden
+    History {
+        topic: String,
+        limit: usize,
+        since: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum RoomResponse {
+    Ack,
+    History(Vec<HistoryEntry>),
+}
+
+/// Everything needed to join a private room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Invitation {
+    pub workspace: String,
+    pub room: String,
+    pub key: RoomKey,
+    pub info: RoomInfo,
+}
+
+/// A room shared as text, with enough addresses to find it without the DHT
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteLink {
+    pub workspace: String,
+    pub room: String,
+    // Only set for private rooms
+    pub key: Option<RoomKey>,
+    pub info: RoomInfo,
+    // Peers to dial first, each ending in their peer id
+    pub addresses: Vec<Multiaddr>,
+}
+
+impl InviteLink {
+    pub fn encode(&self) -> Result<String, Box<dyn Error + Send>> {
+        let bytes = serde_cbor::to_vec(self).map_err(|e| -> Box<dyn Error + Send> {
+            Box::new(io::Error::new(io::ErrorKind::InvalidData, e))
+        })?;
+        Ok(format!(
+            "{INVITE_LINK_PREFIX}{}",
+            bs58::encode(bytes).into_string()
+        ))
+    }
+
+    pub fn decode(link: &str) -> Result<Self, Box<dyn Error + Send>> {
+        let invalid = || -> Box<dyn Error + Send> {
+            Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Not a valid invite link",
+            ))
+        };
+        let encoded = link
+            .trim()
+            .strip_prefix(INVITE_LINK_PREFIX)
+            .ok_or_else(invalid)?;
+        let bytes = bs58::decode(encoded).into_vec().map_err(|_| invalid())?;
+        serde_cbor::from_slice(&bytes).map_err(|_| invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invite_links_round_trip() {
+        let link = InviteLink {
+            workspace: "test".to_string(),
+            room: "chat".to_string(),
+            key: Some(RoomKey::generate()),
+            info: RoomInfo::default(),
+            addresses: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+        };
+        let decoded = InviteLink::decode(&link.encode().unwrap()).unwrap();
+        assert_eq!(decoded.workspace, link.workspace);
+        assert_eq!(decoded.room, link.room);
+        assert_eq!(decoded.key, link.key);
+        assert_eq!(decoded.addresses, link.addresses);
+    }
+
+    #[test]
+    fn decode_rejects_other_text() {
+        assert!(InviteLink::decode("chat").is_err());
+        assert!(InviteLink::decode("swapbytes:0OIl").is_err());
+        assert!(InviteLink::decode("swapbytes:abc").is_err());
+    }
+}
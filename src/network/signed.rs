@@ -0,0 +1,36 @@
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// A value signed with a peer's identity key, so it can be checked after being passed on by others.
+/// Ordered by its bytes, which gives every peer the same tie-break between two signed values.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) struct Signed {
+    data: Vec<u8>,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Signed {
+    pub(crate) fn sign<T: Serialize>(keypair: &Keypair, value: &T) -> Option<Self> {
+        let data = serde_cbor::to_vec(value).ok()?;
+        let signature = keypair.sign(&data).ok()?;
+        Some(Self {
+            data,
+            public_key: keypair.public().encode_protobuf(),
+            signature,
+        })
+    }
+
+    /// The value and the peer that signed it, if the signature is valid
+    pub(crate) fn verify<T: DeserializeOwned>(&self) -> Option<(PeerId, T)> {
+        let public_key = PublicKey::try_decode_protobuf(&self.public_key).ok()?;
+        if !public_key.verify(&self.data, &self.signature) {
+            return None;
+        }
+        let value = serde_cbor::from_slice(&self.data).ok()?;
+        Some((public_key.to_peer_id(), value))
+    }
+}
@@ -0,0 +1,147 @@
+use libp2p::gossipsub::{self, TopicScoreParams};
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Longest chat message, in bytes, that peers will accept and relay
+pub(crate) const MAX_MESSAGE_LENGTH: usize = 2048;
+
+// Each peer can send a burst of messages, then one message per refill interval
+const RATE_LIMIT_BURST: f64 = 10.0;
+const RATE_LIMIT_REFILL: Duration = Duration::from_secs(1);
+
+/// Reasons a message is not relayed to the rest of the room
+#[derive(Debug, PartialEq)]
+pub(crate) enum Invalid {
+    TooLong,
+    Empty,
+    Malformed,
+    // A private room message that can't be decrypted with the room key
+    WrongKey,
+    // Not signed by its author, or signed for another room
+    Forged,
+}
+
+/// Check that a chat message is something a SwapBytes peer could have sent
+pub(crate) fn validate_text(text: &str) -> Result<(), Invalid> {
+    if text.len() > MAX_MESSAGE_LENGTH {
+        return Err(Invalid::TooLong);
+    }
+
+    if text.trim().is_empty() {
+        return Err(Invalid::Empty);
+    }
+
+    // Control characters could be used to mess with other peers' terminals
+    if text.chars().any(|c| c.is_control()) {
+        return Err(Invalid::Malformed);
+    }
+
+    Ok(())
+}
+
+/// Token bucket per peer, so a single peer can't flood a room
+#[derive(Default)]
+pub(crate) struct RateLimiter {
+    buckets: HashMap<PeerId, Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    throttled: bool,
+}
+
+/// Outcome of checking a message against the rate limit
+#[derive(Debug, PartialEq)]
+pub(crate) enum RateLimit {
+    Allowed,
+    // The peer has just gone over the limit
+    Throttled,
+    // The peer was already over the limit
+    StillThrottled,
+}
+
+impl RateLimiter {
+    pub(crate) fn check(&mut self, peer_id: PeerId) -> RateLimit {
+        let now = Instant::now();
+        let bucket = self.buckets.entry(peer_id).or_insert(Bucket {
+            tokens: RATE_LIMIT_BURST,
+            last_refill: now,
+            throttled: false,
+        });
+
+        let refilled =
+            now.duration_since(bucket.last_refill).as_secs_f64() / RATE_LIMIT_REFILL.as_secs_f64();
+        bucket.tokens = (bucket.tokens + refilled).min(RATE_LIMIT_BURST);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.throttled = false;
+            RateLimit::Allowed
+        } else if bucket.throttled {
+            RateLimit::StillThrottled
+        } else {
+            bucket.throttled = true;
+            RateLimit::Throttled
+        }
+    }
+
+    pub(crate) fn remove(&mut self, peer_id: &PeerId) {
+        self.buckets.remove(peer_id);
+    }
+}
+
+/// Gossipsub config that waits for the application to validate each message before relaying it
+pub(crate) fn gossipsub_config() -> Result<gossipsub::Config, gossipsub::ConfigBuilderError> {
+    gossipsub::ConfigBuilder::default()
+        .validate_messages()
+        // Leave room for the message envelope on top of the text itself
+        .max_transmit_size(16 * MAX_MESSAGE_LENGTH)
+        .build()
+}
+
+/// Score parameters for each room topic
+pub(crate) fn topic_score_params() -> TopicScoreParams {
+    TopicScoreParams {
+        topic_weight: 1.0,
+        // Chat rooms are often quiet, so don't penalise peers for not delivering messages
+        mesh_message_deliveries_weight: 0.0,
+        mesh_failure_penalty_weight: 0.0,
+        // Three rejected messages in a short time is enough to be graylisted
+        invalid_message_deliveries_weight: -10.0,
+        invalid_message_deliveries_decay: 0.9,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_text_rejects_what_peers_would_not_send() {
+        assert_eq!(validate_text("hello"), Ok(()));
+        assert_eq!(validate_text("  "), Err(Invalid::Empty));
+        assert_eq!(validate_text("\x1b[2J"), Err(Invalid::Malformed));
+        let long = "a".repeat(MAX_MESSAGE_LENGTH + 1);
+        assert_eq!(validate_text(&long), Err(Invalid::TooLong));
+    }
+
+    #[test]
+    fn rate_limiter_throttles_after_a_burst() {
+        let mut limiter = RateLimiter::default();
+        let peer = PeerId::random();
+        for _ in 0..RATE_LIMIT_BURST as usize {
+            assert_eq!(limiter.check(peer), RateLimit::Allowed);
+        }
+        assert_eq!(limiter.check(peer), RateLimit::Throttled);
+        assert_eq!(limiter.check(peer), RateLimit::StillThrottled);
+
+        // Other peers have their own bucket
+        assert_eq!(limiter.check(PeerId::random()), RateLimit::Allowed);
+        limiter.remove(&peer);
+        assert_eq!(limiter.check(peer), RateLimit::Allowed);
+    }
+}
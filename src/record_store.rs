@@ -0,0 +1,247 @@
+use crate::logger;
+use crate::storage;
+
+use libp2p::{
+    kad::{
+        store::{MemoryStore, RecordStore, Result},
+        ProviderRecord, Record, RecordKey,
+    },
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const RECORDS_FILE: &str = "records";
+/// How often changes are written to disk
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Kademlia record store that keeps a copy of every record in the data directory.
+///
+/// Records are served from memory. Changes are batched up and written to disk by a
+/// background thread when `flush` is called, so the room registry and nicknames
+/// survive a restart without slowing down the event loop.
+pub struct DiskStore {
+    inner: MemoryStore,
+    // MemoryStore can only list providers by key, so remember which keys have any
+    provider_keys: HashSet<RecordKey>,
+    // Whether anything has changed since the last flush
+    dirty: bool,
+    writer: Option<(mpsc::Sender<SavedRecords>, JoinHandle<()>)>,
+}
+
+// Instants can't be saved, so expiry times are stored as seconds since the epoch
+#[derive(Default, Serialize, Deserialize)]
+struct SavedRecords {
+    records: Vec<SavedRecord>,
+    providers: Vec<SavedProvider>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedRecord {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    publisher: Option<Vec<u8>>,
+    expires: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedProvider {
+    key: Vec<u8>,
+    provider: Vec<u8>,
+    expires: Option<u64>,
+    addresses: Vec<Vec<u8>>,
+}
+
+impl DiskStore {
+    /// Load the records saved in the data directory, skipping any that have expired
+    pub fn new(local_id: PeerId, data_dir: PathBuf) -> Self {
+        let saved: SavedRecords = storage::load(&data_dir, RECORDS_FILE);
+
+        // Writes happen on their own thread so a slow disk never holds up the swarm
+        let (sender, receiver) = mpsc::channel::<SavedRecords>();
+        let writer = thread::spawn(move || {
+            while let Ok(mut saved) = receiver.recv() {
+                // Only the latest copy matters if the writes have fallen behind
+                while let Ok(newer) = receiver.try_recv() {
+                    saved = newer;
+                }
+                if let Err(e) = storage::save(&data_dir, RECORDS_FILE, &saved) {
+                    logger::error!("Unable to save records: {:?}", e);
+                }
+            }
+        });
+
+        let mut store = Self {
+            inner: MemoryStore::new(local_id),
+            provider_keys: HashSet::new(),
+            dirty: false,
+            writer: Some((sender, writer)),
+        };
+
+        let now = Instant::now();
+
+        for saved_record in saved.records {
+            let record = Record {
+                key: RecordKey::from(saved_record.key),
+                value: saved_record.value,
+                publisher: saved_record
+                    .publisher
+                    .and_then(|publisher| PeerId::from_bytes(&publisher).ok()),
+                expires: saved_record.expires.map(to_instant),
+            };
+            if record.is_expired(now) {
+                continue;
+            }
+            if let Err(e) = store.inner.put(record) {
+                logger::error!("Unable to load record: {:?}", e);
+            }
+        }
+
+        for saved_provider in saved.providers {
+            let provider = match PeerId::from_bytes(&saved_provider.provider) {
+                Ok(provider) => provider,
+                Err(_) => continue,
+            };
+            let record = ProviderRecord {
+                key: RecordKey::from(saved_provider.key),
+                provider,
+                expires: saved_provider.expires.map(to_instant),
+                addresses: saved_provider
+                    .addresses
+                    .into_iter()
+                    .filter_map(|addr| Multiaddr::try_from(addr).ok())
+                    .collect(),
+            };
+            if record.is_expired(now) {
+                continue;
+            }
+            store.provider_keys.insert(record.key.clone());
+            if let Err(e) = store.inner.add_provider(record) {
+                logger::error!("Unable to load provider record: {:?}", e);
+            }
+        }
+
+        store
+    }
+
+    /// Hand everything changed since the last flush to the background writer
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        let records = self
+            .inner
+            .records()
+            .map(|record| SavedRecord {
+                key: record.key.to_vec(),
+                value: record.value.clone(),
+                publisher: record.publisher.map(|publisher| publisher.to_bytes()),
+                expires: record.expires.map(to_unix_secs),
+            })
+            .collect();
+
+        let providers = self
+            .provider_keys
+            .iter()
+            .flat_map(|key| self.inner.providers(key))
+            .map(|record| SavedProvider {
+                key: record.key.to_vec(),
+                provider: record.provider.to_bytes(),
+                expires: record.expires.map(to_unix_secs),
+                addresses: record.addresses.iter().map(|addr| addr.to_vec()).collect(),
+            })
+            .collect();
+
+        let saved = SavedRecords { records, providers };
+        if let Some((sender, _)) = &self.writer {
+            let _ = sender.send(saved);
+        }
+    }
+}
+
+// Write out any last changes, and wait for them to reach the disk
+impl Drop for DiskStore {
+    fn drop(&mut self) {
+        self.flush();
+        if let Some((sender, writer)) = self.writer.take() {
+            drop(sender);
+            let _ = writer.join();
+        }
+    }
+}
+
+impl RecordStore for DiskStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.inner.get(k)
+    }
+
+    fn put(&mut self, r: Record) -> Result<()> {
+        self.inner.put(r)?;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        self.inner.remove(k);
+        self.dirty = true;
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.inner.records()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> Result<()> {
+        let key = record.key.clone();
+        self.inner.add_provider(record)?;
+        self.provider_keys.insert(key);
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.inner.providers(key)
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.inner.provided()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        self.inner.remove_provider(k, p);
+        if self.inner.providers(k).is_empty() {
+            self.provider_keys.remove(k);
+        }
+        self.dirty = true;
+    }
+}
+
+fn to_unix_secs(instant: Instant) -> u64 {
+    let now = Instant::now();
+    let time = match instant.checked_duration_since(now) {
+        Some(remaining) => SystemTime::now() + remaining,
+        None => SystemTime::now() - now.duration_since(instant),
+    };
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn to_instant(unix_secs: u64) -> Instant {
+    let now = Instant::now();
+    let expires = UNIX_EPOCH + std::time::Duration::from_secs(unix_secs);
+    match expires.duration_since(SystemTime::now()) {
+        Ok(remaining) => now + remaining,
+        // Already expired, which `is_expired` will pick up
+        Err(e) => now.checked_sub(e.duration()).unwrap_or(now),
+    }
+}
@@ -0,0 +1,133 @@
+use crate::config::{self, Config};
+use crate::logger;
+use crate::record_store::{DiskStore, SAVE_INTERVAL};
+use crate::storage;
+use crate::transport;
+
+use futures::StreamExt;
+use libp2p::{
+    autonat,
+    core::multiaddr::Protocol,
+    identify,
+    kad::{self, Mode},
+    ping, relay,
+    swarm::{NetworkBehaviour, SwarmEvent},
+};
+use std::error::Error;
+use std::time::Duration;
+
+#[derive(NetworkBehaviour)]
+struct RelayBehaviour {
+    relay: relay::Behaviour,
+    identify: identify::Behaviour,
+    ping: ping::Behaviour,
+    kademlia: kad::Behaviour<DiskStore>,
+    autonat: autonat::Behaviour,
+}
+
+/// Run a circuit relay server.
+///
+/// Peers that cannot dial each other directly reserve a slot on the relay and
+/// are reachable through it. The relay also acts as a Kademlia server so peers
+/// on different subnets can find each other without mDNS.
+pub async fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    let key = storage::load_keypair(&config.data_dir.join("relay.key"))?;
+
+    let psk = match &config.psk {
+        Some(path) => Some(transport::load_psk(path)?),
+        None => None,
+    };
+
+    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(key)
+        .with_tokio()
+        .with_other_transport(|key| transport::build(key, psk))?
+        .with_behaviour(|key| {
+            let relay_config = relay::Config {
+                // Allow chat sessions and file transfers to run through the relay
+                max_circuit_duration: Duration::from_secs(60 * 60),
+                max_circuit_bytes: 0,
+                ..Default::default()
+            };
+
+            RelayBehaviour {
+                relay: relay::Behaviour::new(key.public().to_peer_id(), relay_config),
+                identify: identify::Behaviour::new(
+                    identify::Config::new("/swapbytes/1.0.0".to_string(), key.public())
+                        .with_agent_version(format!(
+                            "swapbytes-relay/{}",
+                            env!("CARGO_PKG_VERSION")
+                        )),
+                ),
+                ping: ping::Behaviour::new(ping::Config::new()),
+                kademlia: kad::Behaviour::new(
+                    key.public().to_peer_id(),
+                    DiskStore::new(key.public().to_peer_id(), config.data_dir.clone()),
+                ),
+                // Answer dial-back requests so peers can learn whether they are reachable
+                autonat: autonat::Behaviour::new(
+                    key.public().to_peer_id(),
+                    autonat::Config {
+                        only_global_ips: false,
+                        ..Default::default()
+                    },
+                ),
+            }
+        })?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60 * 60)))
+        .build();
+
+    swarm.behaviour_mut().kademlia.set_mode(Some(Mode::Server));
+
+    for addr in config.listen_addrs() {
+        if psk.is_some() && config::is_quic(&addr) {
+            println!("Unable to listen on {addr}: QUIC can't be used in a private network");
+            continue;
+        }
+        if let Err(e) = swarm.listen_on(addr.clone()) {
+            println!("Unable to listen on {addr}: {e}");
+        }
+    }
+
+    println!("Relay running with peer id {}", swarm.local_peer_id());
+
+    let mut save_interval = tokio::time::interval(SAVE_INTERVAL);
+    loop {
+        let event = tokio::select! {
+            event = swarm.select_next_some() => event,
+            _ = save_interval.tick() => {
+                swarm.behaviour_mut().kademlia.store_mut().flush();
+                continue;
+            }
+        };
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                let address = address.with(Protocol::P2p(*swarm.local_peer_id()));
+                logger::info!("Relay listening on {address}");
+                println!("Listening on {address}");
+            }
+            // Make every identified peer discoverable through Kademlia
+            SwarmEvent::Behaviour(RelayBehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+            })) => {
+                swarm.add_external_address(info.observed_addr);
+                for addr in info.listen_addrs {
+                    swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+                }
+            }
+            SwarmEvent::Behaviour(RelayBehaviourEvent::Relay(event)) => {
+                logger::info!("Relay event: {:?}", event);
+            }
+            SwarmEvent::ListenerError { error, .. } => {
+                println!("Listener error: {error}");
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                logger::info!("Connection established with {peer_id}");
+            }
+            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+                logger::info!("Connection closed to {peer_id}");
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,14 +1,21 @@
 use crate::{
-    logger,
-    network::{Client, RequestType},
+    config, logger,
+    network::{
+        Action, Client, Invitation, InviteLink, NetworkInfo, RequestType, RoomDetails, RoomInfo,
+        RoomKey, RoomNames, MAX_DESCRIPTION_LENGTH, MAX_NAME_LENGTH, MAX_OTHER_NAMES,
+    },
+    storage,
 };
 use lazy_static::lazy_static;
-use libp2p::{gossipsub::IdentTopic, PeerId};
+use libp2p::{autonat::NatStatus, gossipsub::IdentTopic, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     error::Error,
     io,
+    path::PathBuf,
     sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::fs;
 
@@ -22,13 +29,108 @@ pub struct App {
     pub topic: IdentTopic,
     pub peer_id: Option<PeerId>,
     pub nicknames: HashMap<PeerId, String>,
+    // When each nickname was last looked up
+    nicknames_fetched: HashMap<PeerId, Instant>,
     pub rooms: Vec<String>,
+    // Rooms we are subscribed to, as opposed to every room in the workspace
+    pub joined_rooms: Vec<String>,
+    pub private_rooms: HashMap<String, PrivateRoom>,
+    // Password rooms from before a restart, waiting for their password to be entered again
+    pub locked_rooms: HashMap<String, RoomInfo>,
+    // Invitations to private rooms that haven't been accepted yet
+    pub pending_invites: HashMap<String, Invitation>,
+    pub room_info: HashMap<String, RoomInfo>,
+    // Number of peers subscribed to each room, updated by the network
+    pub room_members: HashMap<String, usize>,
+    // Topic and pinned messages for each joined room
+    pub room_details: HashMap<String, RoomDetails>,
+    // Names given to renamed rooms, by room id
+    pub room_names: HashMap<String, RoomNames>,
+    // Chat messages in each room that a moderator could still delete
+    pub posted: HashMap<String, Vec<PostedMessage>>,
+    // Rooms we have been kicked or banned from, which are left on the UI's next update
+    pub removed_from: Vec<String>,
+    // Messages, and messages mentioning us, that arrived in each room while we were elsewhere
+    pub unread: HashMap<String, usize>,
+    pub mentions: HashMap<String, usize>,
+    // Position of the first unread message in each room, where a divider is shown
+    pub new_messages_from: HashMap<String, usize>,
+    // Rooms pinned to the top of the Select Room tab, saved per workspace
+    pub favourite_rooms: Vec<String>,
+    // Rooms and categories whose messages aren't counted as unread, saved per workspace
+    pub muted_rooms: Vec<String>,
+    // When the last message in each room arrived, in seconds since the unix epoch
+    pub room_activity: HashMap<String, u64>,
     pub private_messages: Vec<(MessageType, String)>,
+    // Index of each chat message in the private session, and when it was sent
+    pub private_posted: Vec<(usize, u64)>,
+    // Show every joined room in one timeline instead of the current room
+    pub show_timeline: bool,
     pub connected_peer: Option<PeerId>,
     pub connected: bool,
     pub requested_file: Option<String>,
     pub requesting_file: bool,
     pub removed_peers: Vec<PeerId>,
+    pub nat_status: NatStatus,
+    pub external_addresses: Vec<Multiaddr>,
+    pub workspace: String,
+    pub network_info: NetworkInfo,
+    pub data_dir: PathBuf,
+    // Problems listening on an address, kept so they can be shown once logged in
+    pub listen_errors: Vec<String>,
+}
+
+const JOINED_ROOMS_FILE: &str = "joined_rooms";
+const PRIVATE_ROOMS_FILE: &str = "private_rooms";
+const FAVOURITE_ROOMS_FILE: &str = "favourite_rooms";
+const MUTED_ROOMS_FILE: &str = "muted_rooms";
+// How long a nickname is trusted before it is looked up again, so renamed peers are picked up
+const NICKNAME_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A private room we hold the key for
+#[derive(Clone)]
+pub struct PrivateRoom {
+    pub key: RoomKey,
+    pub info: RoomInfo,
+    // Anyone with the password can join, otherwise an invitation is needed
+    pub password: bool,
+}
+
+// Password room keys aren't saved, as they are as good as the password itself
+#[derive(Serialize, Deserialize)]
+struct SavedPrivateRoom {
+    key: Option<RoomKey>,
+    info: RoomInfo,
+    password: bool,
+}
+
+/// A chat message shown in a room, and who sent it
+#[derive(Clone)]
+pub struct PostedMessage {
+    // Gossipsub message id, which is the same on every peer
+    pub id: String,
+    pub author: PeerId,
+    // Position in the room's messages
+    pub index: usize,
+    // Seconds since the unix epoch, used to put earlier messages from other peers in order
+    pub sent_at: u64,
+}
+
+/// A chat message sent before we joined a room, from another member's history
+pub struct HistoryMessage {
+    pub id: String,
+    pub author: PeerId,
+    pub text: String,
+    pub sent_at: u64,
+}
+
+/// A chat message shown in the combined timeline
+pub struct TimelineMessage {
+    // None for the private session
+    pub room: Option<String>,
+    pub message_type: MessageType,
+    pub text: String,
+    pub sent_at: u64,
 }
 
 #[derive(Clone, PartialEq)]
@@ -44,6 +146,8 @@ pub enum MessageType {
     Info,
     Error,
     Help,
+    // A message that mentions our nickname
+    Mention,
 }
 
 impl App {
@@ -52,18 +156,43 @@ impl App {
             messages: HashMap::new(),
             nickname: String::new(),
             nicknames: HashMap::new(),
+            nicknames_fetched: HashMap::new(),
             screen: Screen::Login,
             quitting: false,
             num_connected_peers: usize::MIN,
             topic: IdentTopic::new(""),
             rooms: vec![],
+            joined_rooms: vec![],
+            private_rooms: HashMap::new(),
+            locked_rooms: HashMap::new(),
+            pending_invites: HashMap::new(),
+            room_info: HashMap::new(),
+            room_members: HashMap::new(),
+            room_details: HashMap::new(),
+            room_names: HashMap::new(),
+            posted: HashMap::new(),
+            removed_from: vec![],
+            unread: HashMap::new(),
+            mentions: HashMap::new(),
+            new_messages_from: HashMap::new(),
+            favourite_rooms: vec![],
+            muted_rooms: vec![],
+            room_activity: HashMap::new(),
             peer_id: None,
             private_messages: vec![],
+            private_posted: vec![],
+            show_timeline: false,
             connected_peer: None,
             connected: false,
             requested_file: None,
             requesting_file: false,
             removed_peers: vec![],
+            nat_status: NatStatus::Unknown,
+            external_addresses: vec![],
+            workspace: String::new(),
+            network_info: NetworkInfo::default(),
+            data_dir: PathBuf::from("data"),
+            listen_errors: vec![],
         }
     }
 
@@ -110,6 +239,7 @@ impl App {
             );
         }
         self.nicknames.remove_entry(&peer_id);
+        self.nicknames_fetched.remove(&peer_id);
 
         // If the peer that expired is the one that we are DMing, then leave the chat.
         if self.connected && self.connected_peer.unwrap() == peer_id {
@@ -145,10 +275,222 @@ impl App {
                 }
             };
         } else {
+            if let MessageType::Message | MessageType::Mention = message_type {
+                self.private_posted
+                    .push((self.private_messages.len(), unix_time()));
+            }
             self.private_messages.push((message_type, message.clone()));
         }
     }
 
+    /// Chat messages from every joined room and the private session, oldest first
+    pub fn timeline(&self) -> Vec<TimelineMessage> {
+        let mut timeline: Vec<TimelineMessage> = self
+            .joined_rooms
+            .iter()
+            .flat_map(|room| {
+                let messages = self.messages.get(room);
+                self.posted
+                    .get(room)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(move |posted| {
+                        let (message_type, text) = messages?.get(posted.index)?;
+                        Some(TimelineMessage {
+                            room: Some(room.clone()),
+                            message_type: message_type.clone(),
+                            text: text.clone(),
+                            sent_at: posted.sent_at,
+                        })
+                    })
+            })
+            .collect();
+
+        if self.connected {
+            timeline.extend(self.private_posted.iter().filter_map(|(index, sent_at)| {
+                let (message_type, text) = self.private_messages.get(*index)?;
+                Some(TimelineMessage {
+                    room: None,
+                    message_type: message_type.clone(),
+                    text: text.clone(),
+                    sent_at: *sent_at,
+                })
+            }));
+        }
+
+        // Stable, so messages sent in the same second stay in order within each room
+        timeline.sort_by_key(|message| message.sent_at);
+        timeline
+    }
+
+    /// Add a peer's chat message to a room, remembering who sent it in case a moderator deletes it
+    pub fn add_room_message(
+        &mut self,
+        room: &String,
+        id: String,
+        author: PeerId,
+        name: &str,
+        text: &str,
+    ) {
+        let index = match self.messages.get(room) {
+            Some(messages) => messages.len(),
+            None => {
+                logger::error!("Unable to push message for room: {:?}", room);
+                return;
+            }
+        };
+
+        let mentioned = self.peer_id != Some(author) && self.mentions_me(text);
+        let message_type = match mentioned {
+            true => MessageType::Mention,
+            false => MessageType::Message,
+        };
+        self.add_message(message_type, format!("{name}: {text}"), Some(room));
+
+        // Count messages that arrive while we are looking at another room or a private session.
+        // Muted rooms only count mentions.
+        if self.connected || self.topic.to_string() != *room {
+            if !self.is_muted(room) {
+                *self.unread.entry(room.clone()).or_default() += 1;
+            }
+            if mentioned {
+                *self.mentions.entry(room.clone()).or_default() += 1;
+            }
+            self.new_messages_from.entry(room.clone()).or_insert(index);
+        }
+        self.room_activity.insert(room.clone(), unix_time());
+        self.posted
+            .entry(room.clone())
+            .or_default()
+            .push(PostedMessage {
+                id,
+                author,
+                index,
+                sent_at: unix_time(),
+            });
+    }
+
+    /// Merge messages sent before we joined a room into its timeline, skipping any we already have
+    pub fn merge_history(&mut self, room: &String, mut history: Vec<HistoryMessage>) {
+        history.sort_by_key(|message| message.sent_at);
+
+        for message in history {
+            let known = self
+                .posted
+                .get(room)
+                .is_some_and(|posted| posted.iter().any(|known| known.id == message.id));
+            if known {
+                continue;
+            }
+
+            let name = self.display_name(&message.author);
+            let messages = match self.messages.get_mut(room) {
+                Some(messages) => messages,
+                None => return,
+            };
+            let posted = self.posted.entry(room.clone()).or_default();
+
+            // Goes before the first message sent after it, or at the end if there isn't one
+            let index = posted
+                .iter()
+                .filter(|known| known.sent_at > message.sent_at)
+                .map(|known| known.index)
+                .min()
+                .unwrap_or(messages.len());
+            messages.insert(
+                index,
+                (MessageType::Message, format!("{name}: {}", message.text)),
+            );
+            for known in posted.iter_mut().filter(|known| known.index >= index) {
+                known.index += 1;
+            }
+            if let Some(divider) = self.new_messages_from.get_mut(room) {
+                if *divider >= index {
+                    *divider += 1;
+                }
+            }
+            posted.push(PostedMessage {
+                id: message.id,
+                author: message.author,
+                index,
+                sent_at: message.sent_at,
+            });
+            let activity = self.room_activity.entry(room.clone()).or_default();
+            *activity = (*activity).max(message.sent_at);
+        }
+    }
+
+    /// Whether a message mentions us, as "@nickname"
+    // Only a whole "@nickname" counts, so @sam doesn't mention sammy
+    fn mentions_me(&self, text: &str) -> bool {
+        if self.nickname.is_empty() {
+            return false;
+        }
+        let text = text.to_lowercase();
+        let mention = format!("@{}", self.nickname.to_lowercase());
+        let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+        text.match_indices(&mention).any(|(start, _)| {
+            let before = text[..start].chars().next_back();
+            let after = text[start + mention.len()..].chars().next();
+            !before.is_some_and(is_word) && !after.is_some_and(is_word)
+        })
+    }
+
+    /// Clear a room's unread counters, leaving the divider until we move to another room
+    pub fn mark_read(&mut self, room: &str) {
+        self.unread.remove(room);
+        self.mentions.remove(room);
+    }
+
+    /// Replace a message a moderator has deleted
+    pub fn delete_message(&mut self, room: &str, id: &str) {
+        let posted = match self.posted.get_mut(room) {
+            Some(posted) => posted,
+            None => return,
+        };
+        let deleted = match posted.iter().position(|message| message.id == id) {
+            Some(position) => posted.remove(position),
+            None => return,
+        };
+
+        let name = self.display_name(&deleted.author);
+        if let Some(message) = self
+            .messages
+            .get_mut(room)
+            .and_then(|messages| messages.get_mut(deleted.index))
+        {
+            *message = (
+                MessageType::Info,
+                format!("{name}: message deleted by a moderator"),
+            );
+        }
+    }
+
+    /// Id of the last message a peer sent in a room that hasn't been deleted
+    pub fn last_message_from(&self, room: &str, author: &PeerId) -> Option<String> {
+        self.posted
+            .get(room)?
+            .iter()
+            .rev()
+            .find(|message| message.author == *author)
+            .map(|message| message.id.clone())
+    }
+
+    /// Report a problem listening on an address, in the current room if logged in
+    pub fn listen_error(&mut self, error: String) {
+        logger::error!("{}", error);
+        self.listen_errors.push(error.clone());
+
+        if self.screen == Screen::Chat {
+            let topic_str = self.topic.to_string();
+            let topic = match self.connected {
+                true => None,
+                false => Some(&topic_str),
+            };
+            self.add_message(MessageType::Error, error, topic);
+        }
+    }
+
     pub fn get_messages(&self) -> Vec<(MessageType, String)> {
         if self.connected {
             return self.private_messages.clone();
@@ -167,66 +509,790 @@ impl App {
     }
 
     pub(crate) async fn add_room(
-        &mut self,
         room: &String,
+        description: String,
+        announcement: bool,
         client: &mut Client,
     ) -> Result<(), Box<dyn Error + Send>> {
-        // Adds a new room, sets the current topic to the new room, and adds join message
+        // Adds a new room, sets the current topic to the new room, and adds join message.
+        // Only we, and publishers we add later, can post in an announcement room.
+        // Methods that wait on the network lock APP themselves, and only between awaits,
+        // as the network event loop needs it too.
 
-        // Add the room to the DHT
-        if self.rooms.contains(&room.clone()) {
+        // Add the room to the registry shared with every peer in the workspace
+        check_room_name(room)?;
+        if description.len() > MAX_DESCRIPTION_LENGTH {
             return Err(Box::new(io::Error::new(
-                io::ErrorKind::AlreadyExists,
-                "Room already exists",
+                io::ErrorKind::InvalidInput,
+                format!("Descriptions can be at most {MAX_DESCRIPTION_LENGTH} bytes"),
             )));
         }
-        self.rooms.push(room.clone());
-        client.add_room(self.rooms.clone()).await?;
-
-        // Get the client to subscribe to the new topic
-        client.change_topic(room.clone()).await?;
+        let info = {
+            let app = APP.lock().unwrap();
+            if app.resolve_room(room).is_some() {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "Room already exists",
+                )));
+            }
+            RoomInfo {
+                description,
+                creator: app.peer_id,
+                creator_nickname: app.nickname.clone(),
+                created_at: unix_time(),
+                announcement,
+                names: None,
+                signature: None,
+            }
+        };
+        let info = client.add_room(room.clone(), info).await?;
+        {
+            let mut app = APP.lock().unwrap();
+            app.rooms.push(room.clone());
+            app.room_info.insert(room.clone(), info);
+        }
 
-        // Join the room
-        self.join_room(room, client).await?;
+        // Join the room, which also subscribes to its topic
+        App::join_room(room, client).await?;
         Ok(())
     }
 
     pub(crate) async fn join_room(
-        &mut self,
         room: &String,
         client: &mut Client,
     ) -> Result<(), Box<dyn Error + Send>> {
         // Subscribe to the new topic
-        if self.topic.to_string() == *room {
+        let current = APP.lock().unwrap().topic.to_string();
+        if current == *room {
             return Ok(());
         }
-        self.topic = IdentTopic::new(room.clone());
+        App::subscribe_room(room, client).await?;
+
+        // The divider in the room we are leaving has been seen
+        let mut app = APP.lock().unwrap();
+        let previous = app.topic.to_string();
+        app.new_messages_from.remove(&previous);
+        app.topic = IdentTopic::new(room.clone());
+        app.mark_read(room);
+        drop(app);
 
-        match self.messages.get(&room.clone()) {
+        Ok(())
+    }
+
+    // Subscribe to a room and remember it, without switching to it
+    async fn subscribe_room(
+        room: &String,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        client.change_topic(room.clone()).await?;
+        let details = client.room_details(room.clone()).await?;
+
+        let mut app = APP.lock().unwrap();
+        app.room_details.insert(room.clone(), details);
+
+        match app.messages.get(&room.clone()) {
             Some(_) => {}
             None => {
                 // Insert into messages if doesn't exist
-                self.messages.insert(room.clone(), Vec::new());
-                self.add_message(
+                app.messages.insert(room.clone(), Vec::new());
+                let nickname = app.nickname.clone();
+                let name = app.room_name(room);
+                app.add_message(
                     MessageType::Info,
-                    format!("Logged in as: {}", self.nickname),
-                    Some(&self.topic.clone().to_string()),
+                    format!("Logged in as: {}", nickname),
+                    Some(room),
                 );
-                self.add_message(
+                app.add_message(
                     MessageType::Info,
-                    format!("Joined chat room: {room}"),
-                    Some(&self.topic.clone().to_string()),
+                    format!("Joined chat room: {}", name),
+                    Some(room),
                 );
-                self.add_message(
+                app.add_message(
                     MessageType::Help,
                     "Type \"/help\" to view all available commands".to_string(),
-                    Some(&self.topic.clone().to_string()),
+                    Some(room),
                 );
             }
         };
 
-        client.change_topic(room.clone()).await?;
+        if !app.joined_rooms.contains(room) {
+            app.joined_rooms.push(room.clone());
+            app.save_joined_rooms();
+        }
+        drop(app);
+
+        Ok(())
+    }
+
+    pub(crate) async fn leave_room(
+        room: &String,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Unsubscribes from a room, moving back to Global if it was the current one
+
+        if room == "Global" {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Global can't be left",
+            )));
+        }
+        let joined = APP.lock().unwrap().joined_rooms.contains(room);
+        if !joined {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Not in room: {room}"),
+            )));
+        }
+
+        client.leave_topic(room.clone()).await?;
+        let current = {
+            let mut app = APP.lock().unwrap();
+            app.joined_rooms.retain(|joined| joined != room);
+            app.messages.remove(room);
+            app.posted.remove(room);
+            app.room_details.remove(room);
+            app.mark_read(room);
+            app.new_messages_from.remove(room);
+            app.save_joined_rooms();
+
+            // Nobody else can see a private room, so forget it entirely
+            if app.private_rooms.remove(room).is_some() {
+                app.rooms.retain(|known| known != room);
+                app.room_info.remove(room);
+                app.save_private_rooms();
+            }
+            app.topic.to_string() == *room
+        };
+
+        if current {
+            App::join_room(&"Global".to_string(), client).await?;
+        }
+        let mut app = APP.lock().unwrap();
+        let topic = app.topic.to_string();
+        let name = app.room_name(room);
+        app.add_message(
+            MessageType::Info,
+            format!("Left room: {}", name),
+            Some(&topic),
+        );
+        drop(app);
+        Ok(())
+    }
+
+    /// Subscribe to the rooms that were joined in this workspace before the last restart
+    pub(crate) async fn rejoin_rooms(client: &mut Client) -> Result<(), Box<dyn Error + Send>> {
+        let (data_dir, workspace, topic) = {
+            let app = APP.lock().unwrap();
+            (
+                app.data_dir.clone(),
+                app.workspace.clone(),
+                app.topic.to_string(),
+            )
+        };
+
+        // Private rooms need their keys before they can be subscribed to
+        let mut saved: HashMap<String, HashMap<String, SavedPrivateRoom>> =
+            storage::load(&data_dir, PRIVATE_ROOMS_FILE);
+        let private_rooms = saved.remove(&workspace).unwrap_or_default();
+        for (room, saved_room) in private_rooms {
+            let private_room = match saved_room {
+                SavedPrivateRoom {
+                    key: Some(key),
+                    info,
+                    password: false,
+                } => PrivateRoom {
+                    key,
+                    info,
+                    password: false,
+                },
+                SavedPrivateRoom { info, .. } => {
+                    let mut app = APP.lock().unwrap();
+                    app.add_message(
+                        MessageType::Info,
+                        format!("Use /join {room} [password] to rejoin {room}"),
+                        Some(&topic),
+                    );
+                    app.locked_rooms.insert(room, info);
+                    continue;
+                }
+            };
+            let owner = private_room.info.verified_creator(&workspace, &room);
+            if let Err(e) = client
+                .add_room_key(room.clone(), private_room.key.clone(), owner)
+                .await
+            {
+                logger::error!("Unable to add the key for {room}: {:?}", e);
+                continue;
+            }
+            let mut app = APP.lock().unwrap();
+            app.room_info
+                .insert(room.clone(), private_room.info.clone());
+            app.private_rooms.insert(room, private_room);
+        }
+
+        let mut saved: HashMap<String, Vec<String>> =
+            storage::load(&data_dir, FAVOURITE_ROOMS_FILE);
+        let favourite_rooms = saved.remove(&workspace).unwrap_or_default();
+        let mut saved: HashMap<String, Vec<String>> = storage::load(&data_dir, MUTED_ROOMS_FILE);
+        let muted_rooms = saved.remove(&workspace).unwrap_or_default();
+        {
+            let mut app = APP.lock().unwrap();
+            app.favourite_rooms = favourite_rooms;
+            app.muted_rooms = muted_rooms;
+        }
+
+        let mut saved: HashMap<String, Vec<String>> = storage::load(&data_dir, JOINED_ROOMS_FILE);
+        let rooms = saved.remove(&workspace).unwrap_or_default();
+
+        // One room refusing us, such as one we have been banned from, shouldn't stop the rest
+        for room in rooms {
+            {
+                let mut app = APP.lock().unwrap();
+                if app.locked_rooms.contains_key(&room) {
+                    continue;
+                }
+                // The registry may not have synced yet
+                if !app.rooms.contains(&room) {
+                    app.rooms.push(room.clone());
+                }
+            }
+            if let Err(e) = App::subscribe_room(&room, client).await {
+                logger::error!("Unable to rejoin {room}: {:?}", e);
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to rejoin {room}: {e}"),
+                    Some(&topic),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn create_private_room(
+        room: &String,
+        password: Option<&str>,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Creates a password protected room, or an invite-only room if there is no password
+
+        check_room_name(room)?;
+        let (workspace, info) = {
+            let app = APP.lock().unwrap();
+            if app.resolve_room(room).is_some() {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "Room already exists",
+                )));
+            }
+            let info = RoomInfo {
+                description: String::new(),
+                creator: app.peer_id,
+                creator_nickname: app.nickname.clone(),
+                created_at: unix_time(),
+                announcement: false,
+                names: None,
+                signature: None,
+            };
+            (app.workspace.clone(), info)
+        };
+
+        let key = match password {
+            Some(password) => password_key(&workspace, room, password).await?,
+            None => RoomKey::generate(),
+        };
+        let private_room = PrivateRoom {
+            key,
+            info: client.sign_room(room.clone(), info).await?,
+            password: password.is_some(),
+        };
+        App::add_private_room(room, private_room, client).await
+    }
+
+    pub(crate) async fn join_private_room(
+        room: &String,
+        password: Option<&str>,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Joins a private room using either its password or an invitation
+
+        let (invitation, workspace) = {
+            let mut app = APP.lock().unwrap();
+            (app.pending_invites.remove(room), app.workspace.clone())
+        };
+
+        let private_room = match (password, invitation) {
+            (Some(password), _) => {
+                // The room's info only decrypts with the right password
+                let key = password_key(&workspace, room, password).await?;
+                let fetched = client.fetch_room_info(room.clone(), key.clone()).await?;
+                let mut app = APP.lock().unwrap();
+                if fetched.is_none() {
+                    let topic = app.topic.to_string();
+                    app.add_message(
+                        MessageType::Info,
+                        format!(
+                            "Nobody has shared {room} yet, so the password couldn't be checked"
+                        ),
+                        Some(&topic),
+                    );
+                }
+                // What we trusted before a restart wins over what the DHT says now
+                let info = app
+                    .locked_rooms
+                    .remove(room)
+                    .or(fetched)
+                    .unwrap_or_default();
+                drop(app);
+                PrivateRoom {
+                    key,
+                    info,
+                    password: true,
+                }
+            }
+            (None, Some(invitation)) => PrivateRoom {
+                key: invitation.key,
+                info: invitation.info,
+                password: false,
+            },
+            (None, None) => {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No invitation to room: {room}"),
+                )))
+            }
+        };
+        App::add_private_room(room, private_room, client).await
+    }
+
+    async fn add_private_room(
+        room: &String,
+        private_room: PrivateRoom,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Only a creator that signed the room's info owns it
+        let workspace = APP.lock().unwrap().workspace.clone();
+        let owner = private_room.info.verified_creator(&workspace, room);
+        client
+            .add_room_key(room.clone(), private_room.key.clone(), owner)
+            .await?;
+        // Lets the next peer with the password check it, and find the room's owner
+        if private_room.password {
+            if let Err(e) = client
+                .publish_room_info(room.clone(), private_room.info.clone())
+                .await
+            {
+                logger::error!("Unable to publish the info for {room}: {:?}", e);
+            }
+        }
+
+        {
+            let mut app = APP.lock().unwrap();
+            if !app.rooms.contains(room) {
+                app.rooms.push(room.clone());
+            }
+            app.room_info
+                .insert(room.clone(), private_room.info.clone());
+            app.private_rooms.insert(room.clone(), private_room);
+            app.save_private_rooms();
+        }
+
+        App::join_room(room, client).await
+    }
 
+    pub(crate) async fn invite(
+        name: &str,
+        room: &String,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Sends a signed invitation containing the room key to another peer
+
+        let (peer_id, invitation) = {
+            let app = APP.lock().unwrap();
+            let peer_id = match app.resolve_peer(name) {
+                Some(peer_id) => peer_id,
+                None => {
+                    return Err(Box::new(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Peer not found: {}", name),
+                    )))
+                }
+            };
+            let private_room = match app.private_rooms.get(room) {
+                Some(private_room) => private_room.clone(),
+                None => {
+                    return Err(Box::new(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Not a private room: {room}"),
+                    )))
+                }
+            };
+
+            let invitation = Invitation {
+                workspace: app.workspace.clone(),
+                room: room.clone(),
+                key: private_room.key,
+                info: private_room.info,
+            };
+            (peer_id, invitation)
+        };
+        client.send_invite(peer_id, invitation).await?;
+
+        let mut app = APP.lock().unwrap();
+        let topic = app.topic.to_string();
+        app.add_message(
+            MessageType::Info,
+            format!("Invited {name} to {room}"),
+            Some(&topic),
+        );
+        drop(app);
+        Ok(())
+    }
+
+    pub(crate) async fn invite_link(
+        room: &String,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Shows a link with the room, its key if it is private, and addresses to reach us on
+
+        let known = APP.lock().unwrap().rooms.contains(room);
+        if !known {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Room not found: {room}"),
+            )));
+        }
+        let addresses = client.invite_addresses().await?;
+
+        let mut app = APP.lock().unwrap();
+        let link = InviteLink {
+            workspace: app.workspace.clone(),
+            room: room.clone(),
+            key: app
+                .private_rooms
+                .get(room)
+                .map(|private_room| private_room.key.clone()),
+            info: app.room_info.get(room).cloned().unwrap_or_default(),
+            addresses,
+        };
+
+        let encoded = link.encode()?;
+        let topic = app.topic.to_string();
+        let name = app.room_name(room);
+        app.add_message(
+            MessageType::Info,
+            format!("Invite link for {name}: {encoded}"),
+            Some(&topic),
+        );
+        if link.addresses.is_empty() {
+            app.add_message(
+                MessageType::Info,
+                "No addresses to share yet, so only peers on this network can use the link"
+                    .to_string(),
+                Some(&topic),
+            );
+        }
+        drop(app);
+        Ok(())
+    }
+
+    pub(crate) async fn join_link(
+        link: &str,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Dials the peers in an invite link, then joins its room, switching workspace if needed
+
+        let link = InviteLink::decode(link)?;
+        if !config::is_valid_workspace(&link.workspace) {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid workspace name in invite link: {}", link.workspace),
+            )));
+        }
+        check_room_name(&link.room)?;
+        // Anyone can edit a link, so only trust room details its creator signed
+        let signed = link
+            .info
+            .verified_creator(&link.workspace, &link.room)
+            .is_some();
+        if !signed && link.room != "Global" {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The room details in the invite link aren't signed by the room's creator",
+            )));
+        }
+        client.dial(link.addresses).await?;
+        App::change_workspace(&link.workspace, client).await?;
+
+        let room = link.room;
+        let joined = APP.lock().unwrap().private_rooms.contains_key(&room);
+        match link.key {
+            Some(key) if !joined => {
+                let private_room = PrivateRoom {
+                    key,
+                    info: link.info,
+                    password: false,
+                };
+                return App::add_private_room(&room, private_room, client).await;
+            }
+            Some(_) => {}
+            None => {
+                {
+                    let mut app = APP.lock().unwrap();
+                    // The registry replaces this once it has synced
+                    if !app.rooms.contains(&room) {
+                        app.rooms.push(room.clone());
+                    }
+                    let names = link.info.verified_names(&app.workspace, &room);
+                    if let Some(names) = names {
+                        app.room_names.entry(room.clone()).or_insert(names);
+                    }
+                    if signed {
+                        app.room_info.entry(room.clone()).or_insert(link.info);
+                    }
+                }
+                client.fetch_rooms().await?;
+            }
+        }
+        App::join_room(&room, client).await
+    }
+
+    // Private room keys are saved for every workspace, so the rooms survive a restart.
+    // Password rooms are saved without their key, so the password is asked for again.
+    fn save_private_rooms(&self) {
+        let mut saved: HashMap<String, HashMap<String, SavedPrivateRoom>> =
+            storage::load(&self.data_dir, PRIVATE_ROOMS_FILE);
+        let joined = self.private_rooms.iter().map(|(room, private_room)| {
+            let saved_room = SavedPrivateRoom {
+                key: (!private_room.password).then(|| private_room.key.clone()),
+                info: private_room.info.clone(),
+                password: private_room.password,
+            };
+            (room.clone(), saved_room)
+        });
+        let locked = self.locked_rooms.iter().map(|(room, info)| {
+            let saved_room = SavedPrivateRoom {
+                key: None,
+                info: info.clone(),
+                password: true,
+            };
+            (room.clone(), saved_room)
+        });
+        saved.insert(self.workspace.clone(), joined.chain(locked).collect());
+        if let Err(e) = storage::save(&self.data_dir, PRIVATE_ROOMS_FILE, &saved) {
+            logger::error!("Unable to save private rooms: {:?}", e);
+        }
+    }
+
+    // Joined rooms are saved for every workspace, so switching back rejoins them
+    fn save_joined_rooms(&self) {
+        let mut saved: HashMap<String, Vec<String>> =
+            storage::load(&self.data_dir, JOINED_ROOMS_FILE);
+        saved.insert(self.workspace.clone(), self.joined_rooms.clone());
+        if let Err(e) = storage::save(&self.data_dir, JOINED_ROOMS_FILE, &saved) {
+            logger::error!("Unable to save joined rooms: {:?}", e);
+        }
+    }
+
+    /// Pin or unpin a room in the Select Room tab, returning whether it is now a favourite
+    pub fn toggle_favourite(&mut self, room: &String) -> bool {
+        let favourite = match self.favourite_rooms.contains(room) {
+            true => {
+                self.favourite_rooms.retain(|known| known != room);
+                false
+            }
+            false => {
+                self.favourite_rooms.push(room.clone());
+                true
+            }
+        };
+
+        self.save_room_list(FAVOURITE_ROOMS_FILE, &self.favourite_rooms);
+        favourite
+    }
+
+    /// Whether we can post in a room, which in announcement rooms is only its owner and publishers
+    pub fn can_post(&self, room: &str) -> bool {
+        let info = match self.room_info.get(room) {
+            Some(info) if info.announcement && !self.private_rooms.contains_key(room) => info,
+            _ => return true,
+        };
+        match self.peer_id {
+            Some(peer_id) => {
+                info.creator == Some(peer_id)
+                    || self
+                        .room_details
+                        .get(room)
+                        .is_some_and(|details| details.publishers.contains(&peer_id))
+            }
+            None => false,
+        }
+    }
+
+    /// Whether a room, or a category it is in, has been muted
+    pub fn is_muted(&self, room: &str) -> bool {
+        let name = self.room_name(room);
+        self.muted_rooms
+            .iter()
+            .any(|muted| in_category(room, muted) || in_category(&name, muted))
+    }
+
+    /// Mute or unmute a room, or every room in a category
+    pub fn set_muted(&mut self, name: &str, muted: bool) -> Result<(), Box<dyn Error + Send>> {
+        if muted {
+            if self.is_muted(name) {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{name} is already muted"),
+                )));
+            }
+            self.muted_rooms.push(name.to_string());
+        } else {
+            if !self.muted_rooms.iter().any(|known| known == name) {
+                let message = match self
+                    .muted_rooms
+                    .iter()
+                    .find(|muted| in_category(name, muted))
+                {
+                    Some(category) => format!("{name} is muted as part of {category}"),
+                    None => format!("{name} isn't muted"),
+                };
+                return Err(Box::new(io::Error::new(io::ErrorKind::NotFound, message)));
+            }
+            self.muted_rooms.retain(|known| known != name);
+        }
+
+        // Rooms inside a newly muted category don't need their own entries
+        let muted_rooms = self.muted_rooms.clone();
+        self.muted_rooms.retain(|room| {
+            !muted_rooms
+                .iter()
+                .any(|category| category != room && in_category(room, category))
+        });
+        self.save_room_list(MUTED_ROOMS_FILE, &self.muted_rooms);
+
+        let topic = self.topic.to_string();
+        let state = if muted { "Muted" } else { "Unmuted" };
+        self.add_message(MessageType::Info, format!("{state} {name}"), Some(&topic));
+        Ok(())
+    }
+
+    pub(crate) async fn join_category(
+        category: &str,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Subscribes to every known room in a category, without leaving the current room
+
+        let (rooms, topic) = {
+            let app = APP.lock().unwrap();
+            let rooms: Vec<String> = app
+                .rooms
+                .iter()
+                .filter(|room| {
+                    let name = app.room_name(room);
+                    name != category && in_category(&name, category)
+                })
+                .filter(|room| !app.joined_rooms.contains(room))
+                .cloned()
+                .collect();
+            (rooms, app.topic.to_string())
+        };
+        if rooms.is_empty() {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No rooms left to join in {category}"),
+            )));
+        }
+
+        // One room refusing us, such as one we are banned from, shouldn't stop the rest
+        for room in &rooms {
+            if let Err(e) = App::subscribe_room(room, client).await {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to join {room}: {e}"),
+                    Some(&topic),
+                );
+            }
+        }
+        let mut app = APP.lock().unwrap();
+        let joined: Vec<String> = rooms
+            .into_iter()
+            .filter(|room| app.joined_rooms.contains(room))
+            .map(|room| app.room_name(&room))
+            .collect();
+        if !joined.is_empty() {
+            app.add_message(
+                MessageType::Info,
+                format!("Joined {}: {}", category, joined.join(", ")),
+                Some(&topic),
+            );
+        }
+        drop(app);
+        Ok(())
+    }
+
+    // Favourite and muted rooms are saved for every workspace
+    fn save_room_list(&self, name: &str, rooms: &[String]) {
+        let mut saved: HashMap<String, Vec<String>> = storage::load(&self.data_dir, name);
+        saved.insert(self.workspace.clone(), rooms.to_vec());
+        if let Err(e) = storage::save(&self.data_dir, name, &saved) {
+            logger::error!("Unable to save {}: {:?}", name, e);
+        }
+    }
+
+    pub(crate) async fn change_workspace(
+        workspace: &String,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Switches to a different workspace, starting again from its Global room
+
+        let current = APP.lock().unwrap().workspace.clone();
+        if current == *workspace {
+            return Ok(());
+        }
+        client.change_workspace(workspace.clone()).await?;
+
+        // Nothing carries over between workspaces
+        let (nickname, peer_id) = {
+            let mut app = APP.lock().unwrap();
+            app.workspace = workspace.clone();
+            app.messages.clear();
+            app.posted.clear();
+            app.removed_from.clear();
+            app.unread.clear();
+            app.mentions.clear();
+            app.new_messages_from.clear();
+            app.favourite_rooms.clear();
+            app.muted_rooms.clear();
+            app.room_activity.clear();
+            app.nicknames.clear();
+            app.nicknames_fetched.clear();
+            app.rooms = vec!["Global".to_string()];
+            app.joined_rooms.clear();
+            app.private_rooms.clear();
+            app.locked_rooms.clear();
+            app.pending_invites.clear();
+            app.room_info.clear();
+            app.room_members.clear();
+            app.room_details.clear();
+            app.room_names.clear();
+            app.topic = IdentTopic::new("");
+            (app.nickname.clone(), app.peer_id)
+        };
+
+        // Nicknames are stored per workspace
+        if let Some(peer_id) = peer_id {
+            client.add_nickname(nickname, peer_id).await?;
+        }
+
+        App::join_room(&"Global".to_string(), client).await?;
+        App::rejoin_rooms(client).await?;
+        APP.lock().unwrap().add_message(
+            MessageType::Info,
+            format!("Switched to workspace: {workspace}"),
+            Some(&"Global".to_string()),
+        );
+
+        client.fetch_rooms().await?;
         Ok(())
     }
 
@@ -278,6 +1344,308 @@ impl App {
         Ok(())
     }
 
+    /// Remember a nickname found in the DHT
+    pub fn set_nickname(&mut self, peer_id: PeerId, nickname: String) {
+        self.nicknames.insert(peer_id, nickname);
+        self.nicknames_fetched.insert(peer_id, Instant::now());
+    }
+
+    /// Whether a known nickname should be looked up again. Only returns true once per
+    /// `NICKNAME_TTL`, so a busy peer doesn't cause a lookup for every message.
+    pub fn nickname_needs_refresh(&mut self, peer_id: &PeerId) -> bool {
+        let stale = self
+            .nicknames_fetched
+            .get(peer_id)
+            .is_none_or(|fetched| fetched.elapsed() >= NICKNAME_TTL);
+        if stale {
+            self.nicknames_fetched.insert(*peer_id, Instant::now());
+        }
+        stale
+    }
+
+    /// The peer's nickname, or a short form of their peer id if it isn't known
+    pub fn display_name(&self, peer_id: &PeerId) -> String {
+        match self.nicknames.get(peer_id) {
+            Some(nickname) => nickname.clone(),
+            None => short_peer_id(peer_id),
+        }
+    }
+
+    /// The name a room is shown with, which is its id until it is renamed
+    pub fn room_name(&self, room: &str) -> String {
+        match self.room_names.get(room) {
+            Some(names) => names.name.clone(),
+            None => room.to_string(),
+        }
+    }
+
+    /// Find a room's id from its name, id, an alias or a name it used to have
+    pub fn resolve_room(&self, name: &str) -> Option<String> {
+        // Current names win over old ones, in case another room has since taken one
+        self.rooms
+            .iter()
+            .find(|room| self.room_name(room) == name)
+            .or_else(|| self.rooms.iter().find(|room| *room == name))
+            .or_else(|| {
+                self.rooms.iter().find(|room| {
+                    self.room_names
+                        .get(*room)
+                        .is_some_and(|names| names.matches(name))
+                })
+            })
+            .cloned()
+    }
+
+    pub(crate) async fn rename_room(
+        name: &str,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Gives the current room a new display name, keeping the old one as a redirect
+
+        let (room, mut names) = {
+            let app = APP.lock().unwrap();
+            let room = app.topic.to_string();
+            let names = app.room_names_for_change(&room, name)?;
+            (room, names)
+        };
+        let old_name = std::mem::replace(&mut names.name, name.to_string());
+        if old_name != room && !names.former_names.contains(&old_name) {
+            names.former_names.push(old_name.clone());
+        }
+        names.former_names.retain(|former| former != name);
+        // Forget the oldest names once there are too many for peers to accept
+        while names.aliases.len() + names.former_names.len() > MAX_OTHER_NAMES {
+            names.former_names.remove(0);
+        }
+        App::set_room_names(&room, names, client).await?;
+
+        APP.lock().unwrap().add_message(
+            MessageType::Info,
+            format!("Renamed {old_name} to {name}"),
+            Some(&room),
+        );
+        Ok(())
+    }
+
+    pub(crate) async fn add_room_alias(
+        alias: &str,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (room, mut names) = {
+            let app = APP.lock().unwrap();
+            let room = app.topic.to_string();
+            let names = app.room_names_for_change(&room, alias)?;
+            (room, names)
+        };
+        if names.aliases.len() >= MAX_OTHER_NAMES {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Rooms can have at most {MAX_OTHER_NAMES} aliases"),
+            )));
+        }
+        names.former_names.retain(|former| former != alias);
+        names.aliases.push(alias.to_string());
+        while names.aliases.len() + names.former_names.len() > MAX_OTHER_NAMES {
+            names.former_names.remove(0);
+        }
+        App::set_room_names(&room, names, client).await?;
+
+        let mut app = APP.lock().unwrap();
+        let name = app.room_name(&room);
+        app.add_message(
+            MessageType::Info,
+            format!("{alias} now leads to {name}"),
+            Some(&room),
+        );
+        drop(app);
+        Ok(())
+    }
+
+    pub(crate) async fn remove_room_alias(
+        alias: &str,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let (room, mut names) = {
+            let app = APP.lock().unwrap();
+            let room = app.topic.to_string();
+            let names = app.current_room_names(&room);
+            if !names.aliases.iter().any(|known| known == alias) {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{alias} isn't an alias of {}", app.room_name(&room)),
+                )));
+            }
+            (room, names)
+        };
+        names.aliases.retain(|known| known != alias);
+        App::set_room_names(&room, names, client).await?;
+
+        APP.lock().unwrap().add_message(
+            MessageType::Info,
+            format!("Removed the alias {alias}"),
+            Some(&room),
+        );
+        Ok(())
+    }
+
+    fn current_room_names(&self, room: &str) -> RoomNames {
+        match self.room_names.get(room) {
+            Some(names) => names.clone(),
+            None => RoomNames::new(&self.workspace, room),
+        }
+    }
+
+    // The room's names, checking a new name is valid and doesn't already lead to a room
+    fn room_names_for_change(
+        &self,
+        room: &str,
+        name: &str,
+    ) -> Result<RoomNames, Box<dyn Error + Send>> {
+        check_room_name(name)?;
+        if room == "Global" || self.private_rooms.contains_key(room) {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} can't be renamed", self.room_name(room)),
+            )));
+        }
+        let names = self.current_room_names(room);
+        if names.name == name || names.aliases.iter().any(|alias| alias == name) {
+            return Err(Box::new(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{name} already leads to {}", names.name),
+            )));
+        }
+        match self.resolve_room(name) {
+            Some(other) if other != room => Err(Box::new(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{name} is already used by {}", self.room_name(&other)),
+            ))),
+            _ => Ok(names),
+        }
+    }
+
+    async fn set_room_names(
+        room: &str,
+        names: RoomNames,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        client
+            .set_room_names(room.to_string(), names.clone())
+            .await?;
+        APP.lock()
+            .unwrap()
+            .room_names
+            .insert(room.to_string(), names);
+        Ok(())
+    }
+
+    /// Find a peer from either their nickname or their peer id
+    pub fn resolve_peer(&self, name: &str) -> Option<PeerId> {
+        self.nicknames
+            .iter()
+            .find_map(|(peer_id, nick)| if nick == name { Some(*peer_id) } else { None })
+            .or_else(|| name.parse::<PeerId>().ok())
+    }
+
+    pub(crate) async fn block_peer(
+        name: &str,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let peer_id = match APP.lock().unwrap().resolve_peer(name) {
+            Some(peer_id) => peer_id,
+            None => {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Peer not found: {}", name),
+                )))
+            }
+        };
+
+        client.block_peer(peer_id).await?;
+
+        let mut app = APP.lock().unwrap();
+        let topic = app.topic.clone();
+        app.add_message(
+            MessageType::Info,
+            format!("Blocked {} ({})", name, peer_id),
+            Some(&topic.to_string()),
+        );
+        drop(app);
+        Ok(())
+    }
+
+    pub(crate) async fn unblock_peer(
+        name: &str,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        let peer_id = match APP.lock().unwrap().resolve_peer(name) {
+            Some(peer_id) => peer_id,
+            None => {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Peer not found: {}", name),
+                )))
+            }
+        };
+
+        client.unblock_peer(peer_id).await?;
+
+        let mut app = APP.lock().unwrap();
+        let topic = app.topic.clone();
+        app.add_message(
+            MessageType::Info,
+            format!("Unblocked {} ({})", name, peer_id),
+            Some(&topic.to_string()),
+        );
+        drop(app);
+        Ok(())
+    }
+
+    pub(crate) async fn fetch_history(
+        limit: usize,
+        since: Option<u64>,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Asks other members of the current room for earlier messages, which are merged in as they arrive
+
+        let room = APP.lock().unwrap().topic.to_string();
+        client.request_history(room.clone(), limit, since).await?;
+        APP.lock().unwrap().add_message(
+            MessageType::Info,
+            format!("Asking peers in {room} for earlier messages"),
+            Some(&room),
+        );
+        Ok(())
+    }
+
+    pub(crate) async fn moderate(
+        action: Action,
+        client: &mut Client,
+    ) -> Result<(), Box<dyn Error + Send>> {
+        // Takes a moderation action in the current room, which fails unless we own or moderate it.
+        // Setting the topic and pinning messages go through here too.
+
+        let room = APP.lock().unwrap().topic.to_string();
+        client.moderate(room.clone(), action.clone()).await?;
+        let details = client.room_details(room.clone()).await?;
+        let mut app = APP.lock().unwrap();
+        app.room_details.insert(room.clone(), details);
+
+        match &action {
+            Action::Delete(id) => app.delete_message(&room, id),
+            _ => {
+                let target = action
+                    .target()
+                    .map(|peer_id| app.display_name(&peer_id))
+                    .unwrap_or_default();
+                let notice = action.notice(&target, &app.nickname);
+                app.add_message(MessageType::Info, notice, Some(&room));
+            }
+        }
+        drop(app);
+        Ok(())
+    }
+
     pub(crate) async fn accept_request(
         &mut self,
         client: &mut Client,
@@ -384,9 +1752,10 @@ impl App {
         }
         self.connected = true;
         self.private_messages = Vec::new();
+        self.private_posted = Vec::new();
 
         // Set the private messages
-        let peer_nickname = self.nicknames.get(&self.connected_peer.unwrap()).unwrap();
+        let peer_nickname = self.display_name(&self.connected_peer.unwrap());
 
         logger::info!("Joining DM with {}", peer_nickname);
 
@@ -473,6 +1842,61 @@ impl App {
     }
 }
 
+/// Whether a room is a category, or is nested somewhere inside it (e.g. "infra/oncall" in "infra")
+pub fn in_category(room: &str, category: &str) -> bool {
+    room == category
+        || room
+            .strip_prefix(category)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+// Rooms can be nested in categories with "/", such as "infra/oncall"
+fn check_room_name(room: &str) -> Result<(), Box<dyn Error + Send>> {
+    if room.split('/').any(|part| part.is_empty()) {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Room names can't start or end with \"/\", or have \"//\" in them",
+        )));
+    }
+    if room.len() > MAX_NAME_LENGTH {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Room names can be at most {MAX_NAME_LENGTH} bytes"),
+        )));
+    }
+    Ok(())
+}
+
+// Deriving a key from a password is slow on purpose, so it runs off the async threads
+async fn password_key(
+    workspace: &str,
+    room: &str,
+    password: &str,
+) -> Result<RoomKey, Box<dyn Error + Send>> {
+    let (workspace, room, password) = (
+        workspace.to_string(),
+        room.to_string(),
+        password.to_string(),
+    );
+    tokio::task::spawn_blocking(move || RoomKey::from_password(&workspace, &room, &password))
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn Error + Send>)
+}
+
+/// The end of a peer id, which is enough to tell peers apart in the UI
+pub fn short_peer_id(peer_id: &PeerId) -> String {
+    let peer_id = peer_id.to_base58();
+    format!("…{}", &peer_id[peer_id.len() - 6..])
+}
+
+/// Seconds since the unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
 lazy_static! {
     pub static ref APP: Arc<Mutex<App>> = Arc::new(Mutex::new(App::new()));
 }
@@ -0,0 +1,51 @@
+use crate::logger;
+
+use libp2p::identity::Keypair;
+use serde::{de::DeserializeOwned, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Load a value saved with `save`, falling back to the default if it doesn't exist yet
+pub fn load<T: DeserializeOwned + Default>(data_dir: &Path, name: &str) -> T {
+    let path = data_dir.join(name);
+    match fs::read(&path) {
+        Ok(bytes) => match serde_cbor::from_slice(&bytes) {
+            Ok(value) => value,
+            Err(e) => {
+                logger::error!("Unable to read {:?}: {:?}", path, e);
+                T::default()
+            }
+        },
+        Err(_) => T::default(),
+    }
+}
+
+/// Save a value in the data directory so it survives a restart
+pub fn save<T: Serialize>(data_dir: &Path, name: &str, value: &T) -> io::Result<()> {
+    fs::create_dir_all(data_dir)?;
+    let bytes =
+        serde_cbor::to_vec(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    // Write to a temporary file first so a crash never leaves a half written file.
+    // Several peers can share a data directory, so each uses its own temporary file.
+    let path = data_dir.join(name);
+    let tmp_path = data_dir.join(format!("{name}.{}.tmp", std::process::id()));
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(tmp_path, path)
+}
+
+/// Load a keypair so the peer id stays the same between restarts, creating it the first time
+pub fn load_keypair(path: &Path) -> Result<Keypair, Box<dyn Error>> {
+    if let Ok(bytes) = fs::read(path) {
+        return Ok(Keypair::from_protobuf_encoding(&bytes)?);
+    }
+
+    let key = Keypair::generate_ed25519();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, key.to_protobuf_encoding()?)?;
+    Ok(key)
+}
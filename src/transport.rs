@@ -0,0 +1,54 @@
+use futures::future::Either;
+use libp2p::{
+    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade::Version},
+    identity::Keypair,
+    noise,
+    pnet::{PnetConfig, PreSharedKey},
+    quic, tcp, yamux, PeerId, Transport,
+};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Build the transport used by both peers and relays.
+///
+/// Without a pre-shared key peers can connect over TCP or QUIC. With a key,
+/// every TCP connection is wrapped in the private network handshake before
+/// noise, so nodes without the key cannot connect at all. QUIC is disabled in
+/// that case as it has its own encryption which cannot be wrapped.
+pub fn build(
+    key: &Keypair,
+    psk: Option<PreSharedKey>,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn Error + Send + Sync>> {
+    let noise_config = noise::Config::new(key)?;
+    // Port reuse is required for TCP hole punching
+    let tcp_transport = tcp::tokio::Transport::new(tcp::Config::default().port_reuse(true));
+
+    let transport = match psk {
+        Some(psk) => tcp_transport
+            .and_then(move |socket, _| PnetConfig::new(psk).handshake(socket))
+            .upgrade(Version::V1Lazy)
+            .authenticate(noise_config)
+            .multiplex(yamux::Config::default())
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .boxed(),
+        None => tcp_transport
+            .upgrade(Version::V1Lazy)
+            .authenticate(noise_config)
+            .multiplex(yamux::Config::default())
+            .or_transport(quic::tokio::Transport::new(quic::Config::new(key)))
+            .map(|output, _| match output {
+                Either::Left((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
+                Either::Right((peer_id, muxer)) => (peer_id, StreamMuxerBox::new(muxer)),
+            })
+            .boxed(),
+    };
+
+    Ok(transport)
+}
+
+/// Read a pre-shared key in the standard `swarm.key` format
+pub fn load_psk(path: &Path) -> Result<PreSharedKey, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(contents.parse::<PreSharedKey>()?)
+}
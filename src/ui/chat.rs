@@ -1,8 +1,10 @@
+use crate::logger;
 use crate::network::Client;
 use crate::ui::tabs;
 
-use crate::APP;
+use crate::{state::App, APP};
 
+use libp2p::autonat::NatStatus;
 use strum::{Display, EnumIter, FromRepr, IntoEnumIterator};
 
 use ratatui::{
@@ -17,6 +19,7 @@ pub struct ChatScreen {
     pub selected_tab: SelectedTab,
     pub chat: tabs::room::Room,
     pub select_room: tabs::select_room::SelectRoom,
+    pub diagnostics: tabs::diagnostics::Diagnostics,
 }
 
 impl ChatScreen {
@@ -24,18 +27,26 @@ impl ChatScreen {
         // As render always gets called, first check if there are no connected peers and we are on the select room tab.
         let app = APP.lock().unwrap();
         if app.num_connected_peers == 0 {
-            self.selected_tab = SelectedTab::Chat;
+            if let SelectedTab::SelectRoom = self.selected_tab {
+                self.selected_tab = SelectedTab::Chat;
+            }
         }
         drop(app);
 
-        let vertical = Layout::vertical([Constraint::Length(1), Constraint::Min(1)]);
-        let [tab_area, content_area] = vertical.areas(frame.area());
+        let vertical = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ]);
+        let [tab_area, content_area, status_area] = vertical.areas(frame.area());
 
         self.render_tabs(frame, tab_area);
+        self.render_status_bar(frame, status_area);
 
         match self.selected_tab {
             SelectedTab::Chat => self.chat.render(frame, content_area),
             SelectedTab::SelectRoom => self.select_room.render(frame, content_area),
+            SelectedTab::Diagnostics => self.diagnostics.render(frame, content_area),
         }
     }
 
@@ -63,10 +74,53 @@ impl ChatScreen {
         frame.render_widget(tab_widget, area);
     }
 
+    fn render_status_bar(&self, frame: &mut Frame, area: Rect) {
+        let app = APP.lock().unwrap();
+        let nat_status = app.nat_status.clone();
+        let external_addresses = app.external_addresses.clone();
+        let workspace = app.workspace.clone();
+        drop(app);
+
+        let (nat_text, nat_color) = match nat_status {
+            NatStatus::Public(_) => ("Public", Color::Green),
+            NatStatus::Private => ("Private", Color::Red),
+            NatStatus::Unknown => ("Unknown", Color::Yellow),
+        };
+
+        let addresses = if external_addresses.is_empty() {
+            "none confirmed".to_string()
+        } else {
+            external_addresses
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let status = Line::from(vec![
+            Span::styled(" Workspace: ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(workspace, Style::default().fg(Color::Cyan)),
+            Span::styled(" | NAT: ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(nat_text, Style::default().fg(nat_color)),
+            Span::styled(" | External: ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(addresses),
+        ]);
+
+        frame.render_widget(Paragraph::new(status), area);
+    }
+
     pub(crate) async fn handle_events(
         &mut self,
         client: &mut Client,
     ) -> Result<(), Box<dyn Error>> {
+        // Leave any room we have been kicked or banned from
+        let removed_from = std::mem::take(&mut APP.lock().unwrap().removed_from);
+        for room in removed_from {
+            if let Err(e) = App::leave_room(&room, client).await {
+                logger::error!("Unable to leave room {room}: {:?}", e);
+            }
+        }
+
         // Handle events for the chat screen
         if event::poll(std::time::Duration::from_millis(16))? {
             if let Event::Key(key) = event::read()? {
@@ -74,9 +128,13 @@ impl ChatScreen {
                     match key.code {
                         // Navigating tabs
                         KeyCode::Tab => {
-                            // If no connected peers, do nothing
+                            // If no connected peers, only the diagnostics tab is available
                             let app = APP.lock().unwrap();
                             if app.num_connected_peers == 0 {
+                                self.selected_tab = match self.selected_tab {
+                                    SelectedTab::Chat => SelectedTab::Diagnostics,
+                                    _ => SelectedTab::Chat,
+                                };
                                 return Ok(());
                             }
 
@@ -106,6 +164,7 @@ impl ChatScreen {
                                     .handle_events(key, client, &mut self.selected_tab)
                                     .await?
                             }
+                            SelectedTab::Diagnostics => self.diagnostics.handle_events(key),
                         },
                     }
                 }
@@ -122,6 +181,8 @@ pub enum SelectedTab {
     Chat,
     #[strum(to_string = "Select Room")]
     SelectRoom,
+    #[strum(to_string = "Diagnostics")]
+    Diagnostics,
 }
 
 impl SelectedTab {
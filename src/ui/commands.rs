@@ -1,6 +1,7 @@
+use crate::config;
 use crate::logger;
-use crate::network::Client;
-use crate::state::MessageType;
+use crate::network::{Action, Client, HISTORY_LIMIT, MAX_MESSAGE_LENGTH};
+use crate::state::{unix_time, App, MessageType};
 use crate::APP;
 
 pub struct Commands {
@@ -21,8 +22,148 @@ impl Default for Commands {
             description: "List all known users that have sent a message".to_string(),
         });
         commands.push(Command {
-            command: "/create_room [room]".to_string(),
-            description: "Create a new room and join it. (e.g. /create_room COSC401)".to_string(),
+            command: "/create_room [room] [description]".to_string(),
+            description:
+                "Create a new room and join it. (e.g. /create_room COSC401 Distributed systems)"
+                    .to_string(),
+        });
+        commands.push(Command {
+            command: "/create_announcement_room [room] [description]".to_string(),
+            description: "Create a room where only you and the publishers you add can post"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/create_private_room [room] [password]".to_string(),
+            description: "Create an encrypted room, invite-only unless a password is given"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/join [room] [password]".to_string(),
+            description:
+                "Join a room, or list the rooms you have joined. Private rooms need a password or an invitation"
+                    .to_string(),
+        });
+        commands.push(Command {
+            command: "/invite [nickname] [room]".to_string(),
+            description: "Invite a peer to a private room (the current one if none is given)"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/invite_link [room]".to_string(),
+            description:
+                "Show a link to a room (the current one if none is given) that anyone can join with"
+                    .to_string(),
+        });
+        commands.push(Command {
+            command: "/join_link [link]".to_string(),
+            description: "Connect to the peers in an invite link and join its room".to_string(),
+        });
+        commands.push(Command {
+            command: "/leave_room [room]".to_string(),
+            description:
+                "Leave a room (the current one if none is given) and stop receiving its messages"
+                    .to_string(),
+        });
+        commands.push(Command {
+            command: "/rename_room [name]".to_string(),
+            description:
+                "Rename the current room, keeping the old name as a redirect (room owners only)"
+                    .to_string(),
+        });
+        commands.push(Command {
+            command: "/alias [alias]".to_string(),
+            description: "Add another name that leads to the current room (room owners only)"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/unalias [alias]".to_string(),
+            description: "Remove one of the current room's aliases (room owners only)".to_string(),
+        });
+        commands.push(Command {
+            command: "/join_category [category]".to_string(),
+            description:
+                "Join every room in a category (e.g. /join_category infra for infra/oncall)"
+                    .to_string(),
+        });
+        commands.push(Command {
+            command: "/mute_room [room|category]".to_string(),
+            description: "Stop counting unread messages in a room or category, except mentions"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/unmute_room [room|category]".to_string(),
+            description: "Count unread messages in a muted room or category again".to_string(),
+        });
+        commands.push(Command {
+            command: "/timeline".to_string(),
+            description:
+                "Show or hide messages from every joined room in one timeline (also <Ctrl+T>)"
+                    .to_string(),
+        });
+        commands.push(Command {
+            command: "/history [count | minutes m]".to_string(),
+            description:
+                "Fetch earlier messages in the current room from other members (e.g. /history 20 or /history 30m)"
+                    .to_string(),
+        });
+        commands.push(Command {
+            command: "/kick [nickname]".to_string(),
+            description: "Remove a peer from the current room (room owners and moderators only)"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/mute [nickname] [minutes]".to_string(),
+            description: "Stop a peer posting in the current room for a number of minutes"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/ban [nickname]".to_string(),
+            description: "Remove a peer from the current room and stop them rejoining".to_string(),
+        });
+        commands.push(Command {
+            command: "/unban [nickname]".to_string(),
+            description: "Lift a peer's ban or mute in the current room".to_string(),
+        });
+        commands.push(Command {
+            command: "/delete [nickname]".to_string(),
+            description: "Delete the last message a peer sent in the current room".to_string(),
+        });
+        commands.push(Command {
+            command: "/mod [nickname]".to_string(),
+            description: "Make a peer a moderator of the current room (room owners only)"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/unmod [nickname]".to_string(),
+            description: "Stop a peer moderating the current room (room owners only)".to_string(),
+        });
+        commands.push(Command {
+            command: "/publisher [nickname]".to_string(),
+            description: "Let a peer post in the current announcement room (room owners only)"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/unpublisher [nickname]".to_string(),
+            description: "Stop a peer posting in the current announcement room (room owners only)"
+                .to_string(),
+        });
+        commands.push(Command {
+            command: "/topic [text]".to_string(),
+            description:
+                "Show the current room's topic, or set it (room owners and moderators only)"
+                    .to_string(),
+        });
+        commands.push(Command {
+            command: "/pin [nickname]".to_string(),
+            description: "Pin the last message a peer sent in the current room".to_string(),
+        });
+        commands.push(Command {
+            command: "/unpin [number]".to_string(),
+            description: "Unpin a message, numbered as in /pins".to_string(),
+        });
+        commands.push(Command {
+            command: "/pins".to_string(),
+            description: "List the messages pinned in the current room".to_string(),
         });
         commands.push(Command {
             command: "/connect [nickname]".to_string(),
@@ -44,6 +185,22 @@ impl Default for Commands {
             command: "/leave".to_string(),
             description: "Leave a private messaging session".to_string(),
         });
+        commands.push(Command {
+            command: "/block [nickname|peer_id]".to_string(),
+            description: "Disconnect from a peer and ignore everything they send".to_string(),
+        });
+        commands.push(Command {
+            command: "/unblock [nickname|peer_id]".to_string(),
+            description: "Allow a blocked peer to connect again".to_string(),
+        });
+        commands.push(Command {
+            command: "/workspace [name]".to_string(),
+            description: "Show the current workspace, or switch to another one".to_string(),
+        });
+        commands.push(Command {
+            command: "/netinfo".to_string(),
+            description: "Show network diagnostics (also live in the Diagnostics tab)".to_string(),
+        });
         Self { commands }
     }
 }
@@ -64,13 +221,35 @@ impl Commands {
         // Handle commands based on the first argument
         match cmd {
             "/help" => self.handle_help().await,
-            "/create_room" => self.handle_create_room(args, client).await,
+            "/create_room" | "/create_announcement_room" => {
+                self.handle_create_room(args, client).await
+            }
             "/list" => self.handle_list(client).await,
+            "/create_private_room" => self.handle_create_private_room(args, client).await,
+            "/join" => self.handle_join(args, client).await,
+            "/invite" => self.handle_invite(args, client).await,
+            "/invite_link" => self.handle_invite_link(args, client).await,
+            "/join_link" => self.handle_join_link(args, client).await,
+            "/timeline" => self.handle_timeline(args).await,
+            "/history" => self.handle_history(args, client).await,
+            "/kick" | "/mute" | "/ban" | "/unban" | "/delete" | "/mod" | "/unmod"
+            | "/publisher" | "/unpublisher" => self.handle_moderation(args, client).await,
+            "/topic" => self.handle_topic(args, client).await,
+            "/pin" | "/unpin" => self.handle_pin(args, client).await,
+            "/pins" => self.handle_pins(args).await,
+            "/leave_room" => self.handle_leave_room(args, client).await,
+            "/join_category" => self.handle_join_category(args, client).await,
+            "/rename_room" | "/alias" | "/unalias" => self.handle_room_names(args, client).await,
+            "/mute_room" | "/unmute_room" => self.handle_mute_room(args).await,
             "/connect" => self.handle_connect(args, client).await,
             "/accept" => self.handle_accept(args, client).await,
             "/reject" => self.handle_reject(args, client).await,
             "/request" => self.handle_request(args, client).await,
             "/leave" => self.handle_leave(client).await,
+            "/workspace" => self.handle_workspace(args, client).await,
+            "/block" => self.handle_block(args, client).await,
+            "/unblock" => self.handle_unblock(args, client).await,
+            "/netinfo" => self.handle_netinfo(args).await,
             _ => {
                 // Command not found, handle error
                 self.handle_command_error(args);
@@ -105,24 +284,509 @@ impl Commands {
     }
 
     async fn handle_create_room(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() < 2 || args[1].len() == 0 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let room = args[1];
+            let description = args[2..].join(" ");
+            let announcement = args[0] == "/create_announcement_room";
+            if let Err(e) =
+                App::add_room(&room.to_string(), description, announcement, client).await
+            {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to add room {room}: {e}"),
+                    Some(&topic_str),
+                );
+            }
+        }
+    }
+
+    async fn handle_create_private_room(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() < 2 || args.len() > 3 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let room = args[1].to_string();
+            let password = args.get(2).copied();
+            if let Err(e) = App::create_private_room(&room, password, client).await {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to create private room: {room}"),
+                    Some(&topic_str),
+                );
+                logger::error!("Error creating private room: {:?}", e);
+            }
+        }
+    }
+
+    async fn handle_invite(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() < 2 || args.len() > 3 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let name = args[1];
+            let room = args
+                .get(2)
+                .map_or(topic_str.clone(), |room| room.to_string());
+            if let Err(e) = App::invite(name, &room, client).await {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to invite {name} to {room}: {e}"),
+                    Some(&topic_str),
+                );
+                logger::error!("Error sending invitation: {:?}", e);
+            }
+        }
+    }
+
+    async fn handle_invite_link(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() > 2 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let room = match args.get(1) {
+                Some(name) => APP
+                    .lock()
+                    .unwrap()
+                    .resolve_room(name)
+                    .unwrap_or_else(|| name.to_string()),
+                None => topic_str.clone(),
+            };
+            if let Err(e) = App::invite_link(&room, client).await {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to create an invite link: {e}"),
+                    Some(&topic_str),
+                );
+                logger::error!("Error creating invite link: {:?}", e);
+            }
+        }
+    }
+
+    async fn handle_join_link(&self, args: Vec<&str>, client: &mut Client) {
+        let (_, connected) = self.current_topic();
+
+        if args.len() != 2 || connected {
+            self.handle_command_error(args.clone());
+        } else if let Err(e) = App::join_link(args[1], client).await {
+            // Joining may have switched workspace before failing
+            let mut app = APP.lock().unwrap();
+            let topic_str = app.topic.clone().to_string();
+            app.add_message(
+                MessageType::Error,
+                format!("Unable to join from link: {e}"),
+                Some(&topic_str),
+            );
+            drop(app);
+            logger::error!("Error joining from invite link: {:?}", e);
+        }
+    }
+
+    async fn handle_timeline(&self, args: Vec<&str>) {
+        let mut app = APP.lock().unwrap();
+
+        if args.len() != 1 {
+            drop(app);
+            self.handle_command_error(args.clone());
+        } else {
+            app.show_timeline = !app.show_timeline;
+            drop(app);
+        }
+    }
+
+    async fn handle_history(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        // Either a number of messages, or a number of minutes ending in "m"
+        let request = match args.get(1) {
+            None => Some((HISTORY_LIMIT, None)),
+            Some(arg) => match arg.strip_suffix('m') {
+                Some(minutes) => minutes.parse::<u64>().ok().map(|minutes| {
+                    (
+                        HISTORY_LIMIT,
+                        Some(unix_time().saturating_sub(minutes.saturating_mul(60))),
+                    )
+                }),
+                None => arg.parse::<usize>().ok().map(|count| (count, None)),
+            },
+        };
+
+        match request {
+            Some((limit, since)) if args.len() <= 2 && !connected => {
+                if let Err(e) = App::fetch_history(limit, since, client).await {
+                    APP.lock().unwrap().add_message(
+                        MessageType::Error,
+                        e.to_string(),
+                        Some(&topic_str),
+                    );
+                    logger::error!("Error fetching history: {:?}", e);
+                }
+            }
+            _ => {
+                self.handle_command_error(args.clone());
+            }
+        }
+    }
+
+    async fn handle_moderation(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, action) = {
+            let mut app = APP.lock().unwrap();
+            let topic_str = app.topic.clone().to_string();
+            let expected_args = match args[0] {
+                "/mute" => 3,
+                _ => 2,
+            };
+
+            if args.len() != expected_args || app.connected {
+                drop(app);
+                self.handle_command_error(args.clone());
+                return;
+            }
+
+            let name = args[1];
+            let peer_id = match app.resolve_peer(name) {
+                Some(peer_id) => peer_id,
+                None => {
+                    app.add_message(
+                        MessageType::Error,
+                        format!("Peer not found: {name}"),
+                        Some(&topic_str),
+                    );
+                    drop(app);
+                    return;
+                }
+            };
+
+            let action = match args[0] {
+                "/kick" => Action::Kick(peer_id),
+                "/ban" => Action::Ban(peer_id),
+                "/unban" => Action::Unban(peer_id),
+                "/mod" => Action::AddModerator(peer_id),
+                "/unmod" => Action::RemoveModerator(peer_id),
+                "/publisher" => Action::AddPublisher(peer_id),
+                "/unpublisher" => Action::RemovePublisher(peer_id),
+                "/mute" => match args[2].parse::<u64>() {
+                    Ok(minutes) if minutes > 0 => Action::Mute(peer_id, unix_time() + minutes * 60),
+                    _ => {
+                        drop(app);
+                        self.handle_command_error(args.clone());
+                        return;
+                    }
+                },
+                _ => match app.last_message_from(&topic_str, &peer_id) {
+                    Some(message_id) => Action::Delete(message_id),
+                    None => {
+                        app.add_message(
+                            MessageType::Error,
+                            format!("No messages from {name} to delete"),
+                            Some(&topic_str),
+                        );
+                        drop(app);
+                        return;
+                    }
+                },
+            };
+
+            (topic_str, action)
+        };
+        if let Err(e) = App::moderate(action, client).await {
+            APP.lock()
+                .unwrap()
+                .add_message(MessageType::Error, e.to_string(), Some(&topic_str));
+            logger::error!("Error moderating room: {:?}", e);
+        }
+    }
+
+    async fn handle_topic(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, topic) = {
+            let mut app = APP.lock().unwrap();
+            let topic_str = app.topic.clone().to_string();
+
+            if app.connected {
+                drop(app);
+                self.handle_command_error(args.clone());
+                return;
+            }
+
+            if args.len() == 1 {
+                let topic = app
+                    .room_details
+                    .get(&topic_str)
+                    .map(|details| details.topic.clone())
+                    .unwrap_or_default();
+                let message = match topic.is_empty() {
+                    true => format!("{topic_str} has no topic"),
+                    false => format!("Topic for {topic_str}: {topic}"),
+                };
+                app.add_message(MessageType::Info, message, Some(&topic_str));
+                drop(app);
+                return;
+            }
+
+            let topic = args[1..].join(" ");
+            if topic.len() > MAX_MESSAGE_LENGTH {
+                app.add_message(
+                    MessageType::Error,
+                    format!("Topics can be at most {MAX_MESSAGE_LENGTH} bytes"),
+                    Some(&topic_str),
+                );
+                drop(app);
+                return;
+            }
+
+            (topic_str, topic)
+        };
+        if let Err(e) = App::moderate(Action::SetTopic(topic), client).await {
+            APP.lock()
+                .unwrap()
+                .add_message(MessageType::Error, e.to_string(), Some(&topic_str));
+            logger::error!("Error setting topic: {:?}", e);
+        }
+    }
+
+    async fn handle_pin(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, action) = {
+            let mut app = APP.lock().unwrap();
+            let topic_str = app.topic.clone().to_string();
+
+            if args.len() != 2 || app.connected {
+                drop(app);
+                self.handle_command_error(args.clone());
+                return;
+            }
+
+            let action = if args[0] == "/unpin" {
+                let pinned = args[1].parse::<usize>().ok().and_then(|number| {
+                    app.room_details
+                        .get(&topic_str)?
+                        .pins
+                        .get(number.checked_sub(1)?)
+                        .cloned()
+                });
+                match pinned {
+                    Some(pinned) => Action::Unpin(pinned.id),
+                    None => {
+                        app.add_message(
+                            MessageType::Error,
+                            format!("No pinned message numbered {}", args[1]),
+                            Some(&topic_str),
+                        );
+                        drop(app);
+                        return;
+                    }
+                }
+            } else {
+                let name = args[1];
+                let pinned = app
+                    .resolve_peer(name)
+                    .and_then(|peer_id| app.last_message_from(&topic_str, &peer_id));
+                match pinned {
+                    Some(message_id) => Action::Pin(message_id),
+                    None => {
+                        app.add_message(
+                            MessageType::Error,
+                            format!("No messages from {name} to pin"),
+                            Some(&topic_str),
+                        );
+                        drop(app);
+                        return;
+                    }
+                }
+            };
+
+            (topic_str, action)
+        };
+        if let Err(e) = App::moderate(action, client).await {
+            APP.lock()
+                .unwrap()
+                .add_message(MessageType::Error, e.to_string(), Some(&topic_str));
+            logger::error!("Error pinning message: {:?}", e);
+        }
+    }
+
+    async fn handle_pins(&self, args: Vec<&str>) {
         let mut app = APP.lock().unwrap();
         let topic_str = app.topic.clone().to_string();
 
-        if args.len() < 2 || args[1].len() == 0 || app.connected {
+        if args.len() != 1 || app.connected {
             drop(app);
             self.handle_command_error(args.clone());
+            return;
+        }
+
+        let pins = app
+            .room_details
+            .get(&topic_str)
+            .map(|details| details.pins.clone())
+            .unwrap_or_default();
+        if pins.is_empty() {
+            app.add_message(
+                MessageType::Info,
+                format!("No messages are pinned in {topic_str}"),
+                Some(&topic_str),
+            );
         } else {
-            let room = args[1];
-            let _ = match app.add_room(&room.to_string(), client).await {
-                Ok(_) => {}
-                Err(_) => {
+            app.add_message(
+                MessageType::Info,
+                format!("Pinned messages in {topic_str}:"),
+                Some(&topic_str),
+            );
+            for (number, pinned) in pins.iter().enumerate() {
+                let line = match (pinned.author, &pinned.text) {
+                    (Some(author), Some(text)) => format!("{}: {text}", app.display_name(&author)),
+                    _ => "(not in your history of this room)".to_string(),
+                };
+                app.add_message(
+                    MessageType::Info,
+                    format!("{}. {line}", number + 1),
+                    Some(&topic_str),
+                );
+            }
+        }
+        drop(app);
+    }
+
+    async fn handle_join(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() == 1 && !connected {
+            let mut app = APP.lock().unwrap();
+            let joined_rooms: Vec<String> = app
+                .joined_rooms
+                .iter()
+                .map(|room| app.room_name(room))
+                .collect();
+            let joined_rooms = joined_rooms.join(", ");
+            app.add_message(
+                MessageType::Info,
+                format!("Joined rooms: {joined_rooms}"),
+                Some(&topic_str),
+            );
+            drop(app);
+        } else if args.len() > 3 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let password = args.get(2).copied();
+            let (room, private) = {
+                let mut app = APP.lock().unwrap();
+                // Old names and aliases lead to the room's id
+                let room = app
+                    .resolve_room(args[1])
+                    .unwrap_or_else(|| args[1].to_string());
+                let private = password.is_some() || app.pending_invites.contains_key(&room);
+                if !private && !app.rooms.contains(&room) {
                     app.add_message(
                         MessageType::Error,
-                        format!("Unable to add room: {room}"),
+                        format!("Unknown room: {room}"),
                         Some(&topic_str),
                     );
+                    return;
                 }
+                (room, private)
             };
+
+            let result = if private {
+                App::join_private_room(&room, password, client).await
+            } else {
+                App::join_room(&room, client).await
+            };
+            if let Err(e) = result {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to join {room}: {e}"),
+                    Some(&topic_str),
+                );
+                logger::error!("Error joining room: {:?}", e);
+            }
+        }
+    }
+
+    async fn handle_leave_room(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() > 2 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let room = match args.get(1) {
+                Some(name) => APP
+                    .lock()
+                    .unwrap()
+                    .resolve_room(name)
+                    .unwrap_or_else(|| name.to_string()),
+                None => topic_str.clone(),
+            };
+            if let Err(e) = App::leave_room(&room, client).await {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to leave room: {e}"),
+                    Some(&topic_str),
+                );
+            }
+        }
+    }
+
+    async fn handle_room_names(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() != 2 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let name = args[1];
+            let result = match args[0] {
+                "/rename_room" => App::rename_room(name, client).await,
+                "/alias" => App::add_room_alias(name, client).await,
+                _ => App::remove_room_alias(name, client).await,
+            };
+            if let Err(e) = result {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    e.to_string(),
+                    Some(&topic_str),
+                );
+                logger::error!("Error changing room names: {:?}", e);
+            }
+        }
+    }
+
+    async fn handle_join_category(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() != 2 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let category = args[1].trim_end_matches('/');
+            if let Err(e) = App::join_category(category, client).await {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    e.to_string(),
+                    Some(&topic_str),
+                );
+                logger::error!("Error joining category: {:?}", e);
+            }
+        }
+    }
+
+    async fn handle_mute_room(&self, args: Vec<&str>) {
+        let mut app = APP.lock().unwrap();
+        let topic_str = app.topic.clone().to_string();
+
+        if args.len() != 2 || app.connected {
+            drop(app);
+            self.handle_command_error(args.clone());
+        } else {
+            // Rooms are muted by id so they stay muted when renamed
+            let name = args[1].trim_end_matches('/');
+            let name = app.resolve_room(name).unwrap_or_else(|| name.to_string());
+            let muted = args[0] == "/mute_room";
+            if let Err(e) = app.set_muted(&name, muted) {
+                app.add_message(MessageType::Error, e.to_string(), Some(&topic_str));
+            }
             drop(app);
         }
     }
@@ -273,6 +937,98 @@ impl Commands {
         }
     }
 
+    async fn handle_workspace(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() == 1 && !connected {
+            let mut app = APP.lock().unwrap();
+            let workspace = app.workspace.clone();
+            app.add_message(
+                MessageType::Info,
+                format!("Current workspace: {workspace}"),
+                Some(&topic_str),
+            );
+            drop(app);
+        } else if args.len() != 2 || !config::is_valid_workspace(args[1]) || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let workspace = args[1];
+            match App::change_workspace(&workspace.to_string(), client).await {
+                Ok(_) => {
+                    logger::info!("Switched to workspace {workspace}");
+                }
+                Err(_) => {
+                    APP.lock().unwrap().add_message(
+                        MessageType::Error,
+                        format!("Unable to switch to workspace: {workspace}"),
+                        Some(&topic_str),
+                    );
+                }
+            };
+        }
+    }
+
+    async fn handle_block(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() != 2 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let name = args[1];
+            if let Err(e) = App::block_peer(name, client).await {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to block: {name}"),
+                    Some(&topic_str),
+                );
+                logger::error!("Error blocking peer: {:?}", e);
+            }
+        }
+    }
+
+    async fn handle_unblock(&self, args: Vec<&str>, client: &mut Client) {
+        let (topic_str, connected) = self.current_topic();
+
+        if args.len() != 2 || connected {
+            self.handle_command_error(args.clone());
+        } else {
+            let name = args[1];
+            if let Err(e) = App::unblock_peer(name, client).await {
+                APP.lock().unwrap().add_message(
+                    MessageType::Error,
+                    format!("Unable to unblock: {name}"),
+                    Some(&topic_str),
+                );
+                logger::error!("Error unblocking peer: {:?}", e);
+            }
+        }
+    }
+
+    async fn handle_netinfo(&self, args: Vec<&str>) {
+        let mut app = APP.lock().unwrap();
+        if args.len() != 1 {
+            drop(app);
+            self.handle_command_error(args.clone());
+            return;
+        }
+
+        let topic_str = app.topic.clone().to_string();
+        let topic = match app.connected {
+            false => Some(&topic_str),
+            true => None,
+        };
+        for line in app.network_info.summary() {
+            app.add_message(MessageType::Info, line, topic);
+        }
+        drop(app);
+    }
+
+    // The current room and whether we are in a private session, without keeping APP locked
+    fn current_topic(&self) -> (String, bool) {
+        let app = APP.lock().unwrap();
+        (app.topic.to_string(), app.connected)
+    }
+
     pub fn handle_command_error(&self, args: Vec<&str>) {
         // Send error message back
         let mut app = APP.lock().unwrap();
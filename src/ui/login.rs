@@ -1,6 +1,6 @@
 use crate::logger;
-use crate::network::Client;
-use crate::state::{Screen, APP};
+use crate::network::{Client, MAX_NAME_LENGTH};
+use crate::state::{App, MessageType, Screen, APP};
 use crate::ui::cursor::Cursor;
 
 use ratatui::{
@@ -153,7 +153,8 @@ impl LoginScreen {
 
     fn enter_char(&mut self, new_char: char) {
         let index = self.byte_index();
-        if new_char != ' ' {
+        // Nicknames are shared with every room we create, so they are kept short
+        if new_char != ' ' && self.input.len() + new_char.len_utf8() <= MAX_NAME_LENGTH {
             self.input.insert(index, new_char);
             self.cursor.move_cursor_right(self.input.chars().count());
         }
@@ -195,9 +196,15 @@ impl LoginScreen {
             client.add_nickname(nickname, peer_id).await?;
 
             // Connect app to global
+            APP.lock().unwrap().rooms.push("Global".to_string());
+            App::join_room(&"Global".to_string(), client).await?;
+            App::rejoin_rooms(client).await?;
+
+            // Show anything that went wrong while starting up
             let mut app = APP.lock().unwrap();
-            app.rooms.push("Global".to_string());
-            app.join_room(&"Global".to_string(), client).await?;
+            for error in app.listen_errors.clone() {
+                app.add_message(MessageType::Error, error, Some(&"Global".to_string()));
+            }
             drop(app);
 
             // Fetch all rooms
@@ -0,0 +1,67 @@
+use ratatui::{
+    crossterm::event::{KeyCode, KeyEvent},
+    prelude::*,
+    widgets::*,
+};
+
+use crate::state::APP;
+
+#[derive(Default)]
+pub struct Diagnostics {
+    scroll: u16,
+}
+
+impl Diagnostics {
+    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+        let block = Block::default()
+            .title(
+                block::Title::from(Span::styled(
+                    "Diagnostics",
+                    Style::default()
+                        .add_modifier(Modifier::BOLD)
+                        .fg(Color::Yellow),
+                ))
+                .alignment(Alignment::Left)
+                .position(block::Position::Top),
+            )
+            .title(
+                block::Title::from(Span::styled(
+                    "Scroll using ↑ and ↓",
+                    Style::default().fg(Color::Yellow),
+                ))
+                .alignment(Alignment::Left)
+                .position(block::Position::Bottom),
+            )
+            .borders(Borders::ALL)
+            .style(Style::default());
+
+        let app = APP.lock().unwrap();
+        let summary = app.network_info.summary();
+        drop(app);
+
+        // Section headings are the only lines that aren't indented
+        let lines: Vec<Line> = summary
+            .into_iter()
+            .map(|line| match line.starts_with(' ') {
+                true => Line::raw(line),
+                false => Line::styled(line, Style::default().add_modifier(Modifier::BOLD)),
+            })
+            .collect();
+
+        // Don't scroll past the last line
+        let max_scroll = (lines.len() as u16).saturating_sub(area.height.saturating_sub(2));
+        self.scroll = self.scroll.min(max_scroll);
+
+        let paragraph = Paragraph::new(lines).block(block).scroll((self.scroll, 0));
+
+        frame.render_widget(paragraph, area);
+    }
+
+    pub(crate) fn handle_events(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Up => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::Down => self.scroll = self.scroll.saturating_add(1),
+            _ => {}
+        }
+    }
+}
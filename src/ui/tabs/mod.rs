@@ -1,2 +1,3 @@
+pub mod diagnostics;
 pub mod room;
 pub mod select_room;
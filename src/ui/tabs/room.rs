@@ -1,19 +1,21 @@
 use crate::network::Client;
 use crate::network::RequestType;
+use crate::network::MAX_MESSAGE_LENGTH;
 use crate::ui::commands::Commands;
 use crate::ui::cursor::Cursor;
 
 use libp2p::gossipsub::PublishError;
 use ratatui::{
-    crossterm::event::{KeyCode, KeyEvent},
+    crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
     prelude::*,
     widgets::*,
 };
 
 use crate::logger;
 use std::error::Error;
+use std::io;
 
-use crate::state::{MessageType, APP};
+use crate::state::{App, MessageType, APP};
 
 pub struct Room {
     pub input: String,
@@ -23,6 +25,8 @@ pub struct Room {
     is_command: bool,
     command_handler: Commands,
     auto_scroll: bool,
+    // Line picked in the timeline, or None to follow the newest message
+    timeline_selected: Option<usize>,
 }
 
 impl Default for Room {
@@ -35,27 +39,86 @@ impl Default for Room {
             is_command: false,
             command_handler: Commands::default(),
             auto_scroll: true,
+            timeline_selected: None,
         }
     }
 }
 
 impl Room {
     pub fn render(&mut self, frame: &mut Frame, area: Rect) {
-        let vertical = Layout::vertical([Constraint::Min(1), Constraint::Length(3)]);
-        let [messages_area, input_area] = vertical.areas(area);
+        let vertical = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(1),
+            Constraint::Length(3),
+        ]);
+        let [rooms_area, messages_area, input_area] = vertical.areas(area);
 
         // RENDER MESSAGES
-        let app = APP.lock().unwrap();
+        let mut app = APP.lock().unwrap();
+        let connected = app.connected;
+        let room = app.topic.to_string();
+        // Anything arriving in the room we are looking at has been read
+        if !connected {
+            app.mark_read(&room);
+        }
         let chat_messages = app.get_messages();
+        let divider = match connected {
+            true => None,
+            false => app.new_messages_from.get(&room).copied(),
+        };
+        let room_bar = get_room_bar(&app, &room, connected);
+        let show_timeline = app.show_timeline;
+        let room_topic = match (show_timeline, connected) {
+            (true, _) => "Timeline of every joined room - <Enter> to jump".to_string(),
+            (false, true) => String::new(),
+            (false, false) => app
+                .room_details
+                .get(&room)
+                .map(|details| details.topic.clone())
+                .unwrap_or_default(),
+        };
+        let timeline = match show_timeline {
+            true => get_timeline_lines(&app),
+            false => vec![],
+        };
         drop(app);
 
+        frame.render_widget(Paragraph::new(room_bar), rooms_area);
+
         let mut lines = vec![];
 
         // Add the chat messages
-        for (message_type, message) in &chat_messages {
+        let chat_messages = match show_timeline {
+            true => vec![],
+            false => chat_messages,
+        };
+        for (index, (message_type, message)) in chat_messages.iter().enumerate() {
+            if divider == Some(index) {
+                lines.push(
+                    Line::from(Span::styled(
+                        "── new messages ──",
+                        Style::default().fg(Color::Red),
+                    ))
+                    .alignment(Alignment::Center),
+                );
+            }
             let styled_line = get_styled_line(message_type.clone(), message);
             lines.push(styled_line);
         }
+        let selected = match show_timeline && !timeline.is_empty() {
+            true => Some(
+                self.timeline_selected
+                    .unwrap_or(timeline.len() - 1)
+                    .min(timeline.len() - 1),
+            ),
+            false => None,
+        };
+        for (index, line) in timeline.into_iter().enumerate() {
+            match selected == Some(index) {
+                true => lines.push(line.style(Style::default().bg(Color::DarkGray))),
+                false => lines.push(line),
+            }
+        }
 
         let messages_content = Text::from(lines.clone());
 
@@ -63,8 +126,16 @@ impl Room {
         let total_message_height = lines.len(); // Total lines of messages, usize
         let visible_area_height = messages_area.height as usize; // Height of the message area in rows, usize
 
-        // Scroll to keep up with messages
-        if ((total_message_height >= visible_area_height - 3) && self.auto_scroll)
+        // Keep the picked timeline line on screen, otherwise scroll to keep up with messages
+        if let Some(selected) = selected {
+            let visible_lines = visible_area_height.saturating_sub(2).max(1);
+            if selected < self.vertical_scroll {
+                self.vertical_scroll = selected;
+            } else if selected >= self.vertical_scroll + visible_lines {
+                self.vertical_scroll = selected + 1 - visible_lines;
+            }
+            self.vertical_scroll_state = self.vertical_scroll_state.position(self.vertical_scroll);
+        } else if ((total_message_height >= visible_area_height - 3) && self.auto_scroll)
             || (self.vertical_scroll.saturating_add(visible_area_height) > total_message_height
                 && self.auto_scroll)
         {
@@ -85,6 +156,14 @@ impl Room {
                         .alignment(Alignment::Left)
                         .position(block::Position::Top),
                     )
+                    .title(
+                        block::Title::from(Span::styled(
+                            room_topic,
+                            Style::default().fg(Color::Cyan),
+                        ))
+                        .alignment(Alignment::Center)
+                        .position(block::Position::Top),
+                    )
                     .title(
                         block::Title::from(Span::styled(
                             "Press <Tab> to swap tabs",
@@ -123,6 +202,7 @@ impl Room {
 
         let app = APP.lock().unwrap();
         let num_peers = app.num_connected_peers;
+        let read_only = !app.connected && !app.can_post(&app.topic.to_string());
         drop(app);
 
         // Render input box
@@ -141,7 +221,10 @@ impl Room {
             .block(
                 Block::bordered()
                     .title(Span::styled(
-                        "Type your message - Press <Enter> to send",
+                        match read_only {
+                            true => "Announcements only - Commands still work",
+                            false => "Type your message - Press <Enter> to send",
+                        },
                         Style::default()
                             .add_modifier(Modifier::BOLD)
                             .fg(Color::Yellow),
@@ -167,7 +250,31 @@ impl Room {
         key: KeyEvent,
         client: &mut Client,
     ) -> Result<(), Box<dyn Error>> {
+        let show_timeline = APP.lock().unwrap().show_timeline;
         match key.code {
+            KeyCode::Char('t') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let mut app = APP.lock().unwrap();
+                app.show_timeline = !app.show_timeline;
+                drop(app);
+                self.timeline_selected = None;
+            }
+            // Jump to the room of the picked timeline line
+            KeyCode::Enter if show_timeline && self.input.is_empty() => {
+                self.jump_to_timeline_room(client).await;
+            }
+            KeyCode::Up if show_timeline => {
+                let length = APP.lock().unwrap().timeline().len();
+                let current = self.timeline_selected.unwrap_or(length.saturating_sub(1));
+                self.timeline_selected = Some(current.min(length).saturating_sub(1));
+            }
+            KeyCode::Down if show_timeline => {
+                let length = APP.lock().unwrap().timeline().len();
+                // Moving past the newest message follows new ones again
+                self.timeline_selected = match self.timeline_selected {
+                    Some(current) if current + 1 < length => Some(current + 1),
+                    _ => None,
+                };
+            }
             // User input
             KeyCode::Char(c) => {
                 self.enter_char(c);
@@ -196,6 +303,15 @@ impl Room {
                                         );
                                     }
                                 }
+                            } else if let Some(io_error) = e.downcast_ref::<io::Error>() {
+                                // Such as being muted by a moderator
+                                let mut app = APP.lock().unwrap();
+                                let topic_str = app.topic.clone().to_string();
+                                app.add_message(
+                                    MessageType::Error,
+                                    io_error.to_string(),
+                                    Some(&topic_str),
+                                );
                             } else {
                                 logger::error!("Unhandled error when submitting message: {:?}", e);
                             }
@@ -231,6 +347,51 @@ impl Room {
         Ok(())
     }
 
+    async fn jump_to_timeline_room(&mut self, client: &mut Client) {
+        let room = {
+            let mut app = APP.lock().unwrap();
+            let timeline = app.timeline();
+            let selected = self
+                .timeline_selected
+                .unwrap_or(timeline.len().saturating_sub(1));
+            let room = match timeline.into_iter().nth(selected) {
+                Some(message) => message.room,
+                None => return,
+            };
+
+            match room {
+                // The private session is already what's shown behind the timeline
+                None => {
+                    app.show_timeline = false;
+                    None
+                }
+                Some(room) if app.connected => {
+                    app.add_message(
+                        MessageType::Error,
+                        format!("Leave the private session with /leave to go back to {room}"),
+                        None,
+                    );
+                    None
+                }
+                Some(room) => Some(room),
+            }
+        };
+
+        if let Some(room) = room {
+            let result = App::join_room(&room, client).await;
+            let mut app = APP.lock().unwrap();
+            match result {
+                Ok(_) => app.show_timeline = false,
+                Err(e) => {
+                    let topic_str = app.topic.to_string();
+                    app.add_message(MessageType::Error, e.to_string(), Some(&topic_str));
+                }
+            }
+        }
+        self.timeline_selected = None;
+        self.auto_scroll = true;
+    }
+
     pub(crate) async fn handle_commands(&mut self, client: &mut Client) {
         // Handle command and clear input
         self.command_handler
@@ -256,10 +417,26 @@ impl Room {
 
         drop(app);
 
+        // Peers reject anything longer, so don't bother sending it
+        if message.len() > MAX_MESSAGE_LENGTH {
+            let mut app = APP.lock().unwrap();
+            let topic_str = topic.to_string();
+            let msg_topic = match connected {
+                true => None,
+                false => Some(&topic_str),
+            };
+            app.add_message(
+                MessageType::Error,
+                format!("Message is too long (max {MAX_MESSAGE_LENGTH} bytes)"),
+                msg_topic,
+            );
+            return Ok(());
+        }
+
         self.input.clear();
         self.cursor.reset_cursor();
 
-        if connected {
+        let message_id = if connected {
             client
                 .send_request(
                     connected_peer.unwrap(),
@@ -268,11 +445,14 @@ impl Room {
                     None,
                 )
                 .await?;
+            None
         } else {
-            client
-                .publish_message(message.clone(), topic.clone())
-                .await?;
-        }
+            Some(
+                client
+                    .publish_message(message.clone(), topic.clone())
+                    .await?,
+            )
+        };
 
         let mut app = APP.lock().unwrap();
         let topic_str = topic.to_string();
@@ -283,7 +463,13 @@ impl Room {
 
         logger::info!("Adding message");
 
-        app.add_message(MessageType::Message, nickname_message, msg_topic);
+        // Room messages are remembered so a moderator can delete them
+        match (message_id, app.peer_id) {
+            (Some(message_id), Some(peer_id)) => {
+                app.add_room_message(&topic_str, message_id, peer_id, &nickname, &message)
+            }
+            _ => app.add_message(MessageType::Message, nickname_message, msg_topic),
+        }
         drop(app);
 
         Ok(())
@@ -354,5 +540,78 @@ fn get_styled_line<'a>(message_type: MessageType, message: &'a str) -> Line<'a>
                 .add_modifier(Modifier::ITALIC)
                 .fg(Color::Cyan),
         )),
+        MessageType::Mention => Line::from(Span::styled(
+            message,
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::Magenta),
+        )),
+    }
+}
+
+// Chat messages from every joined room, prefixed with where they were sent
+fn get_timeline_lines(app: &App) -> Vec<Line<'static>> {
+    let private_name = app
+        .connected_peer
+        .map(|peer_id| format!("@{}", app.display_name(&peer_id)))
+        .unwrap_or_default();
+
+    app.timeline()
+        .into_iter()
+        .map(|message| {
+            let (label, color) = match &message.room {
+                Some(room) => (app.room_name(room), Color::Cyan),
+                None => (private_name.clone(), Color::Green),
+            };
+            let mut line = get_styled_line(message.message_type, &message.text);
+            let spans: Vec<Span<'static>> = line
+                .spans
+                .drain(..)
+                .map(|span| Span::styled(span.content.into_owned(), span.style))
+                .collect();
+            let mut prefixed = vec![Span::styled(
+                format!("[{label}] "),
+                Style::default().fg(color),
+            )];
+            prefixed.extend(spans);
+            Line::from(prefixed)
+        })
+        .collect()
+}
+
+// Every joined room, with the number of unread messages and mentions in each
+fn get_room_bar(app: &App, current: &str, connected: bool) -> Line<'static> {
+    let mut spans = vec![Span::styled(
+        " Rooms: ",
+        Style::default().add_modifier(Modifier::BOLD),
+    )];
+
+    for (i, room) in app.joined_rooms.iter().enumerate() {
+        if i > 0 {
+            spans.push(Span::raw(" | "));
+        }
+        let style = match room == current && !connected {
+            true => Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::Yellow),
+            false => Style::default(),
+        };
+        spans.push(Span::styled(app.room_name(room), style));
+
+        if let Some(unread) = app.unread.get(room) {
+            spans.push(Span::styled(
+                format!(" ({unread})"),
+                Style::default().fg(Color::Cyan),
+            ));
+        }
+        if let Some(mentions) = app.mentions.get(room) {
+            spans.push(Span::styled(
+                format!(" @{mentions}"),
+                Style::default()
+                    .add_modifier(Modifier::BOLD)
+                    .fg(Color::Magenta),
+            ));
+        }
     }
+    Line::from(spans)
 }
@@ -1,26 +1,168 @@
 use crate::{network::Client, ui::chat::SelectedTab};
 use ratatui::{
-    crossterm::event::{KeyCode, KeyEvent},
+    crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
     prelude::*,
     widgets::*,
 };
+use std::collections::HashSet;
 use std::error::Error;
+use strum::{Display, EnumIter, FromRepr, IntoEnumIterator};
 
-use crate::state::APP;
+use crate::state::{in_category, short_peer_id, unix_time, App, MessageType, APP};
 
 pub struct SelectRoom {
     list_state: ListState,
+    // Typed text that rooms are fuzzy matched against
+    filter: String,
+    sort_by: SortBy,
+    // Categories folded away in the room tree
+    collapsed: HashSet<String>,
 }
 
 impl Default for SelectRoom {
     fn default() -> Self {
         let mut list_state = ListState::default();
         list_state.select(Some(0));
-        Self { list_state }
+        Self {
+            list_state,
+            filter: String::new(),
+            sort_by: SortBy::default(),
+            collapsed: HashSet::new(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Display, FromRepr, EnumIter)]
+enum SortBy {
+    #[default]
+    #[strum(to_string = "name")]
+    Name,
+    #[strum(to_string = "activity")]
+    Activity,
+    #[strum(to_string = "members")]
+    Members,
+}
+
+impl SortBy {
+    fn next(self) -> Self {
+        let next_index = (self as usize + 1) % Self::iter().count();
+        Self::from_repr(next_index).unwrap_or(self)
     }
 }
 
+// A line in the room tree
+enum Row {
+    Room { room: String, depth: usize },
+    // Every room named "{path}/..."
+    Category { path: String, depth: usize },
+}
+
 impl SelectRoom {
+    /// The rooms matching the filter in the order they are shown, favourites first
+    fn visible_rooms(&self, app: &App) -> Vec<String> {
+        let mut rooms: Vec<String> = app
+            .rooms
+            .iter()
+            .filter(|room| {
+                // Aliases match too, so a room can be found by its short name
+                let aliases = app.room_names.get(*room).map(|names| &names.aliases);
+                fuzzy_match(&self.filter, &app.room_name(room))
+                    || aliases.is_some_and(|aliases| {
+                        aliases.iter().any(|alias| fuzzy_match(&self.filter, alias))
+                    })
+            })
+            .cloned()
+            .collect();
+
+        rooms.sort_by(|a, b| {
+            let not_favourite = |room: &String| !app.favourite_rooms.contains(room);
+            let by = match self.sort_by {
+                SortBy::Name => {
+                    let name = |room: &String| app.room_name(room).to_lowercase();
+                    name(a).cmp(&name(b))
+                }
+                // Most recent or busiest first
+                SortBy::Activity => {
+                    let activity = |room: &String| app.room_activity.get(room).copied();
+                    activity(b).cmp(&activity(a))
+                }
+                SortBy::Members => {
+                    let members = |room: &String| app.room_members.get(room).copied();
+                    members(b).cmp(&members(a))
+                }
+            };
+            not_favourite(a)
+                .cmp(&not_favourite(b))
+                .then(by)
+                .then_with(|| a.cmp(b))
+        });
+        rooms
+    }
+
+    /// The lines shown in the tab: favourites first, then every other room nested under its categories
+    fn visible_rows(&self, app: &App) -> Vec<Row> {
+        let (favourites, rooms): (Vec<String>, Vec<String>) = self
+            .visible_rooms(app)
+            .into_iter()
+            .partition(|room| app.favourite_rooms.contains(room));
+
+        let mut rows: Vec<Row> = favourites
+            .into_iter()
+            .map(|room| Row::Room { room, depth: 0 })
+            .collect();
+        self.push_tree(app, "", &rooms, 0, &mut rows);
+        rows
+    }
+
+    // Group rooms by the next part of their name, keeping the order they were sorted in
+    fn push_tree(
+        &self,
+        app: &App,
+        prefix: &str,
+        rooms: &[String],
+        depth: usize,
+        rows: &mut Vec<Row>,
+    ) {
+        // (room id or category path, whether it is a category, rooms inside the category)
+        let mut entries: Vec<(String, bool, Vec<String>)> = vec![];
+        for room in rooms {
+            let name = app.room_name(room);
+            match name[prefix.len()..].split_once('/') {
+                Some((category, _)) => {
+                    let path = format!("{prefix}{category}");
+                    match entries
+                        .iter_mut()
+                        .find(|(name, is_category, _)| *is_category && *name == path)
+                    {
+                        Some((_, _, children)) => children.push(room.clone()),
+                        None => entries.push((path, true, vec![room.clone()])),
+                    }
+                }
+                None => entries.push((room.clone(), false, vec![])),
+            }
+        }
+
+        for (name, is_category, children) in entries {
+            if !is_category {
+                rows.push(Row::Room { room: name, depth });
+                continue;
+            }
+            // Everything is shown while filtering, so matches aren't hidden
+            let expanded = !self.filter.is_empty() || !self.collapsed.contains(&name);
+            let prefix = format!("{name}/");
+            rows.push(Row::Category { path: name, depth });
+            if expanded {
+                self.push_tree(app, &prefix, &children, depth + 1, rows);
+            }
+        }
+    }
+
+    fn selected_row(&self, app: &App) -> Option<Row> {
+        let index = self.list_state.selected()?;
+        let mut rows = self.visible_rows(app);
+        (index < rows.len()).then(|| rows.swap_remove(index))
+    }
+
     pub fn render(&mut self, frame: &mut Frame, area: Rect) {
         let block = Block::default()
             .title(
@@ -41,6 +183,25 @@ impl SelectRoom {
                 .alignment(Alignment::Left)
                 .position(block::Position::Bottom),
             )
+            .title(
+                block::Title::from(Span::styled(
+                    format!(
+                        "Filter: {}_  Sort ({}) <Ctrl+S>  Favourite <Ctrl+F>",
+                        self.filter, self.sort_by
+                    ),
+                    Style::default().fg(Color::Yellow),
+                ))
+                .alignment(Alignment::Right)
+                .position(block::Position::Top),
+            )
+            .title(
+                block::Title::from(Span::styled(
+                    "Fold <Enter> ← →  Join category <Ctrl+A>  Mute <Ctrl+N>",
+                    Style::default().fg(Color::Yellow),
+                ))
+                .alignment(Alignment::Center)
+                .position(block::Position::Bottom),
+            )
             .title(
                 block::Title::from(Span::styled(
                     "Confirm change using <Enter>",
@@ -53,18 +214,23 @@ impl SelectRoom {
             .style(Style::default());
 
         let app = APP.lock().unwrap();
-        let rooms = app.rooms.clone();
+        let items: Vec<ListItem> = self
+            .visible_rows(&app)
+            .iter()
+            .map(|row| match row {
+                Row::Room { room, depth } => room_item(&app, room, *depth),
+                Row::Category { path, depth } => {
+                    let collapsed = self.filter.is_empty() && self.collapsed.contains(path);
+                    category_item(&app, path, *depth, collapsed)
+                }
+            })
+            .collect();
         drop(app);
 
         let layout = Layout::default()
             .constraints([Constraint::Percentage(100)].as_ref())
             .split(area);
 
-        let items: Vec<ListItem> = rooms
-            .iter()
-            .map(|room| ListItem::new(room.as_str()))
-            .collect();
-
         let list = List::new(items)
             .block(block)
             .highlight_style(Style::default().fg(Color::Yellow))
@@ -95,26 +261,261 @@ impl SelectRoom {
             KeyCode::Down => {
                 self.list_state.select_next();
             }
-            // Confirm room change
+            // Confirm room change, or fold a category
             KeyCode::Enter => {
-                let selected_room_index = match self.list_state.selected() {
-                    Some(room) => room,
-                    None => 0,
+                let app = APP.lock().unwrap();
+                let room = match self.selected_row(&app) {
+                    Some(Row::Room { room, .. }) => room,
+                    Some(Row::Category { path, .. }) => {
+                        if !self.collapsed.remove(&path) {
+                            self.collapsed.insert(path);
+                        }
+                        return Ok(());
+                    }
+                    None => return Ok(()),
                 };
-
-                let mut app = APP.lock().unwrap();
-                let rooms = app.rooms.clone();
-                app.join_room(&rooms[selected_room_index], client)
-                    .await
-                    .unwrap();
                 drop(app);
+                // Rooms can refuse us, such as one we are banned from
+                if let Err(e) = App::join_room(&room, client).await {
+                    let mut app = APP.lock().unwrap();
+                    let topic_str = app.topic.to_string();
+                    app.add_message(MessageType::Error, e.to_string(), Some(&topic_str));
+                }
 
-                // Move to the chat screen
+                // Move to the chat screen, where any error is shown
+                self.filter.clear();
+                self.list_state.select(Some(0));
                 *selected_tab = SelectedTab::Chat;
             }
+            KeyCode::Left | KeyCode::Right => {
+                let app = APP.lock().unwrap();
+                if let Some(Row::Category { path, .. }) = self.selected_row(&app) {
+                    match key.code {
+                        KeyCode::Left => self.collapsed.insert(path),
+                        _ => self.collapsed.remove(&path),
+                    };
+                }
+                drop(app);
+            }
+            // Pin or unpin the selected room
+            KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let mut app = APP.lock().unwrap();
+                if let Some(Row::Room { room, .. }) = self.selected_row(&app) {
+                    app.toggle_favourite(&room);
+                }
+                drop(app);
+            }
+            // Join every room in the selected category
+            KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let selected = self.selected_row(&APP.lock().unwrap());
+                if let Some(Row::Category { path, .. }) = selected {
+                    if let Err(e) = App::join_category(&path, client).await {
+                        let mut app = APP.lock().unwrap();
+                        let topic = app.topic.to_string();
+                        app.add_message(MessageType::Error, e.to_string(), Some(&topic));
+                    }
+                }
+            }
+            // Mute or unmute the selected room or category
+            KeyCode::Char('n') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let mut app = APP.lock().unwrap();
+                let name = match self.selected_row(&app) {
+                    Some(Row::Room { room, .. }) => room,
+                    Some(Row::Category { path, .. }) => path,
+                    None => return Ok(()),
+                };
+                let muted = app.is_muted(&name);
+                if let Err(e) = app.set_muted(&name, !muted) {
+                    let topic = app.topic.to_string();
+                    app.add_message(MessageType::Error, e.to_string(), Some(&topic));
+                }
+                drop(app);
+            }
+            KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.sort_by = self.sort_by.next();
+                self.list_state.select(Some(0));
+            }
+            // Typing filters the rooms
+            KeyCode::Char(c) => {
+                self.filter.push(c);
+                self.list_state.select(Some(0));
+            }
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.list_state.select(Some(0));
+            }
             _ => {}
         }
 
         Ok(())
     }
 }
+
+// A room, with its last name part indented under its category
+fn room_item<'a>(app: &App, room: &String, depth: usize) -> ListItem<'a> {
+    let detail_style = Style::default().fg(Color::DarkGray);
+    let indent = "  ".repeat(depth);
+    let name = app.room_name(room);
+    let label = match depth {
+        0 => name.clone(),
+        _ => name.rsplit('/').next().unwrap_or(&name).to_string(),
+    };
+    let members = app.room_members.get(room).copied().unwrap_or(0);
+    let marker = match app.favourite_rooms.contains(room) {
+        true => Span::styled("★ ", Style::default().fg(Color::Yellow)),
+        false => Span::raw("  "),
+    };
+    let mut title = vec![
+        Span::raw(indent.clone()),
+        marker,
+        Span::styled(label, Style::default().add_modifier(Modifier::BOLD)),
+        Span::styled(
+            format!("  {members} member{}", if members == 1 { "" } else { "s" }),
+            detail_style,
+        ),
+    ];
+    if *room == app.topic.to_string() {
+        title.push(Span::styled("  current", Style::default().fg(Color::Green)));
+    } else if app.joined_rooms.contains(room) {
+        title.push(Span::styled("  joined", Style::default().fg(Color::Green)));
+    }
+    if app
+        .room_info
+        .get(room)
+        .is_some_and(|info| info.announcement)
+    {
+        title.push(Span::styled(
+            "  announcements",
+            Style::default().fg(Color::Blue),
+        ));
+    }
+    if app.private_rooms.contains_key(room) {
+        title.push(Span::styled(
+            "  private",
+            Style::default().fg(Color::Magenta),
+        ));
+    }
+    if app.is_muted(room) {
+        title.push(Span::styled("  muted", detail_style));
+    }
+    title.extend(unread_spans(
+        app.unread.get(room).copied().unwrap_or(0),
+        app.mentions.get(room).copied().unwrap_or(0),
+    ));
+    let mut lines = vec![Line::from(title)];
+
+    if let Some(names) = app.room_names.get(room) {
+        if !names.aliases.is_empty() {
+            lines.push(Line::styled(
+                format!("{indent}     Also known as {}", names.aliases.join(", ")),
+                detail_style,
+            ));
+        }
+    }
+
+    // Details only arrive once the registry has been synced
+    if let Some(info) = app.room_info.get(room) {
+        if !info.description.is_empty() {
+            lines.push(Line::styled(
+                format!("{indent}     {}", info.description),
+                detail_style,
+            ));
+        }
+        if let Some(creator) = info.creator {
+            lines.push(Line::styled(
+                format!(
+                    "{indent}     Created by {} ({}) {}",
+                    info.creator_nickname,
+                    short_peer_id(&creator),
+                    format_age(unix_time().saturating_sub(info.created_at))
+                ),
+                detail_style,
+            ));
+        }
+    }
+    ListItem::new(lines)
+}
+
+// A category, with the unread counts of every room inside it
+fn category_item<'a>(app: &App, path: &str, depth: usize, collapsed: bool) -> ListItem<'a> {
+    let detail_style = Style::default().fg(Color::DarkGray);
+    let rooms: Vec<&String> = app
+        .rooms
+        .iter()
+        .filter(|room| {
+            let name = app.room_name(room);
+            name != path && in_category(&name, path)
+        })
+        .collect();
+    let count = |counts: &std::collections::HashMap<String, usize>| -> usize {
+        rooms.iter().filter_map(|room| counts.get(*room)).sum()
+    };
+    let label = match depth {
+        0 => path,
+        _ => path.rsplit('/').next().unwrap_or(path),
+    };
+
+    let mut title = vec![
+        Span::raw("  ".repeat(depth)),
+        Span::styled(
+            format!("{} {label}/", if collapsed { "▸" } else { "▾" }),
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::Blue),
+        ),
+        Span::styled(
+            format!(
+                "  {} room{}",
+                rooms.len(),
+                if rooms.len() == 1 { "" } else { "s" }
+            ),
+            detail_style,
+        ),
+    ];
+    if app.is_muted(path) {
+        title.push(Span::styled("  muted", detail_style));
+    }
+    title.extend(unread_spans(count(&app.unread), count(&app.mentions)));
+    ListItem::new(Line::from(title))
+}
+
+fn unread_spans<'a>(unread: usize, mentions: usize) -> Vec<Span<'a>> {
+    let mut spans = vec![];
+    if unread > 0 {
+        spans.push(Span::styled(
+            format!("  {unread} unread"),
+            Style::default().fg(Color::Cyan),
+        ));
+    }
+    if mentions > 0 {
+        spans.push(Span::styled(
+            format!(
+                "  {mentions} mention{}",
+                if mentions == 1 { "" } else { "s" }
+            ),
+            Style::default()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::Magenta),
+        ));
+    }
+    spans
+}
+
+// How long ago something happened, e.g. "5m ago"
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+// Whether every character of the filter appears in the room name in order, ignoring case
+fn fuzzy_match(filter: &str, room: &str) -> bool {
+    let mut chars = room.chars().flat_map(char::to_lowercase);
+    filter
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|wanted| chars.any(|c| c == wanted))
+}